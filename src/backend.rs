@@ -0,0 +1,12 @@
+use crate::grammar::Grammar;
+use failure::Fail;
+
+/// Something that turns a `Grammar` into the representation used by a
+/// particular speech recognizer, such as `DragonBackend` for Dragon's
+/// binary grammar format and `FstBackend` for OpenFST text format.
+pub trait GrammarBackend {
+    type Output;
+    type Error: Fail;
+
+    fn compile(&self, grammar: &Grammar) -> Result<Self::Output, Self::Error>;
+}
@@ -0,0 +1,193 @@
+//! Prints the contents of a compiled grammar, either read directly from
+//! a file or produced by compiling a JSON grammar file, along with the
+//! files it includes. Grammar source is only read as JSON, since the
+//! crate has no text syntax for grammars; any file that does not start
+//! with `{` is taken to be a compiled grammar.
+//!
+//! Usage: stentorian-dump <grammar.json | compiled grammar>
+
+#[cfg(all(windows, target_arch = "x86", target_env = "msvc"))]
+mod dump {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use stentorian::grammarcompiler::decompiler::{
+        parse_binary, parse_id_chunk, parse_rule_chunk, Binary, Chunk,
+    };
+    use stentorian::grammarcompiler::{
+        compile_command_grammar, ChunkType, ImportedRule, NestedPosition, RuleToken,
+    };
+    use stentorian::loader::load;
+
+    pub fn main() {
+        let path = match env::args().nth(1) {
+            Some(path) => path,
+            None => {
+                eprintln!("usage: stentorian-dump <grammar.json | compiled grammar>");
+                process::exit(2);
+            }
+        };
+
+        if let Err(message) = run(&path) {
+            eprintln!("stentorian-dump: {}", message);
+            process::exit(1);
+        }
+    }
+
+    fn run(path: &str) -> Result<(), String> {
+        let data = fs::read(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+
+        let is_json = data
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .map_or(false, |&b| b == b'{');
+
+        let compiled = if is_json {
+            let loaded = load(path).map_err(|e| e.to_string())?;
+            compile_command_grammar(&loaded.grammar).map_err(|e| e.to_string())?
+        } else {
+            data
+        };
+
+        let binary = parse_binary(&compiled).map_err(|e| e.to_string())?;
+        dump(&binary).map_err(|e| e.to_string())
+    }
+
+    type Table = HashMap<u32, String>;
+
+    fn id_table(binary: &Binary, chunk_type: ChunkType) -> Result<Table, String> {
+        match binary.chunk(chunk_type) {
+            Some(chunk) => Ok(parse_id_chunk(chunk)
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .collect()),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    fn dump(binary: &Binary) -> Result<(), String> {
+        println!(
+            "grammar type {}, flags {:#x}, {} chunks",
+            binary.grammar_type,
+            binary.flags,
+            binary.chunks.len()
+        );
+
+        let exports = id_table(binary, ChunkType::Exports)?;
+        let imports = id_table(binary, ChunkType::Imports)?;
+        let lists = id_table(binary, ChunkType::Lists)?;
+        let words = id_table(binary, ChunkType::Words)?;
+
+        let rule_count = match binary.chunk(ChunkType::Rules) {
+            Some(chunk) => parse_rule_chunk(chunk).map_err(|e| e.to_string())?.len(),
+            None => 0,
+        };
+
+        for chunk in binary.chunks.iter() {
+            println!();
+            print_chunk_header(chunk);
+
+            match chunk.known_type() {
+                Some(ChunkType::Rules) => {
+                    print_rules(chunk, &exports, &imports, &lists, &words)?;
+                }
+                Some(ChunkType::Imports) => {
+                    for (id, name) in parse_id_chunk(chunk).map_err(|e| e.to_string())? {
+                        match ImportedRule::from_name(&name) {
+                            Some(rule) => println!(
+                                "  {:>5}  {} (built-in, {} rules + offset {})",
+                                id,
+                                name,
+                                rule_count,
+                                rule.offset()
+                            ),
+                            None => println!("  {:>5}  {}", id, name),
+                        }
+                    }
+                }
+                Some(_) => {
+                    for (id, name) in parse_id_chunk(chunk).map_err(|e| e.to_string())? {
+                        println!("  {:>5}  {}", id, name);
+                    }
+                }
+                None => println!("  ({} bytes of unknown data)", chunk.data.len()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn print_chunk_header(chunk: &Chunk) {
+        let name = match chunk.known_type() {
+            Some(t) => format!("{:?}", t),
+            None => "Unknown".to_owned(),
+        };
+
+        println!(
+            "chunk {} (type {:#x}) at offset {}, {} bytes",
+            name,
+            chunk.chunk_type,
+            chunk.offset,
+            chunk.data.len()
+        );
+    }
+
+    fn print_rules(
+        chunk: &Chunk,
+        exports: &Table,
+        imports: &Table,
+        lists: &Table,
+        words: &Table,
+    ) -> Result<(), String> {
+        let lookup = |table: &Table, id: u32| {
+            table
+                .get(&id)
+                .map_or_else(|| "<undeclared>".to_owned(), |n| format!("{:?}", n))
+        };
+
+        for (id, tokens) in parse_rule_chunk(chunk).map_err(|e| e.to_string())? {
+            match exports.get(&id) {
+                Some(name) => println!("  rule {} (exported as {:?})", id, name),
+                None => println!("  rule {}", id),
+            }
+
+            let mut depth = 2;
+            for t in tokens.iter() {
+                if let RuleToken::Nested(NestedPosition::End, _) = *t {
+                    depth -= 1;
+                }
+
+                let indent = "  ".repeat(depth.max(0) as usize);
+                match *t {
+                    RuleToken::Nested(NestedPosition::Start, ty) => {
+                        println!("{}{:?} start", indent, ty);
+                        depth += 1;
+                    }
+                    RuleToken::Nested(NestedPosition::End, ty) => {
+                        println!("{}{:?} end", indent, ty);
+                    }
+                    RuleToken::Word(w) => println!("{}word {} {}", indent, w, lookup(words, w)),
+                    RuleToken::List(l) => println!("{}list {} {}", indent, l, lookup(lists, l)),
+                    RuleToken::Rule(r) => match imports.get(&r) {
+                        Some(name) => println!("{}rule {} (imported {:?})", indent, r, name),
+                        None => println!("{}rule {}", indent, r),
+                    },
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(windows, target_arch = "x86", target_env = "msvc"))]
+fn main() {
+    dump::main();
+}
+
+#[cfg(not(all(windows, target_arch = "x86", target_env = "msvc")))]
+fn main() {
+    eprintln!("stentorian-dump: stentorian is only available on 32-bit Windows (MSVC)");
+    std::process::exit(1);
+}
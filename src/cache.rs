@@ -0,0 +1,433 @@
+//! On-disk cache of compiled grammars.
+//!
+//! Entries are keyed by a fingerprint of the grammar and the version of
+//! this crate, so entries written by a different version are never
+//! used. Every entry also contains the grammar it was compiled from, so
+//! a fingerprint collision results in a recompilation rather than the
+//! wrong grammar.
+//!
+//! Entries are evicted least recently used first, by their modification
+//! time, which is updated whenever an entry is read.
+
+use crate::grammar::{Element, Grammar};
+use crate::grammarcompiler::compile_command_grammar;
+use crate::grammarcompiler::errors::GrammarError;
+use crate::resultparser::{ListContents, Matcher, MatcherError};
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+const EXTENSION: &str = "cache";
+const TEMPORARY_EXTENSION: &str = "tmp";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// writing an entry takes far less than this, so a temporary file that is
+// older was left behind by a process that crashed
+const STALE_TEMPORARY_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Fail, Debug)]
+pub enum CacheError {
+    #[fail(display = "could not access grammar cache {}: {}", path, cause)]
+    Io {
+        path: String,
+        #[cause]
+        cause: io::Error,
+    },
+    #[fail(display = "could not serialize cache entry: {}", _0)]
+    Serialization(#[cause] serde_json::Error),
+    #[fail(display = "{}", _0)]
+    Grammar(#[cause] GrammarError),
+    #[fail(display = "{}", _0)]
+    Matcher(#[cause] MatcherError),
+}
+
+impl From<GrammarError> for CacheError {
+    fn from(e: GrammarError) -> CacheError {
+        CacheError::Grammar(e)
+    }
+}
+
+impl From<MatcherError> for CacheError {
+    fn from(e: MatcherError) -> CacheError {
+        CacheError::Matcher(e)
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, CacheError>;
+
+/// Computes a hash of the contents of a grammar. Unlike the hashers in
+/// the standard library the result is the same across builds and
+/// platforms, and unlike hashing the JSON representation it does not
+/// depend on the order of fields.
+pub fn fingerprint(grammar: &Grammar) -> u64 {
+    let mut hasher = Fingerprint::new();
+
+    hasher.write_usize(grammar.rules.len());
+    for r in grammar.rules.iter() {
+        hasher.write_str(&r.name);
+        hasher.write_bool(r.exported);
+        hasher.write_element(&r.definition);
+    }
+
+    hasher.0
+}
+
+// 64-bit FNV-1a
+struct Fingerprint(u64);
+
+impl Fingerprint {
+    fn new() -> Self {
+        Fingerprint(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes.iter() {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_tag(&mut self, tag: u8) {
+        self.write(&[tag]);
+    }
+
+    fn write_bool(&mut self, b: bool) {
+        self.write_tag(b as u8);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write(&(n as u64).to_le_bytes());
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_usize(s.len());
+        self.write(s.as_bytes());
+    }
+
+    fn write_option_usize(&mut self, n: Option<usize>) {
+        match n {
+            Some(n) => {
+                self.write_tag(1);
+                self.write_usize(n);
+            }
+            None => self.write_tag(0),
+        }
+    }
+
+    fn write_children(&mut self, children: &[Element]) {
+        self.write_usize(children.len());
+        for c in children.iter() {
+            self.write_element(c);
+        }
+    }
+
+    fn write_element(&mut self, element: &Element) {
+        match *element {
+            Element::Sequence { ref children } => {
+                self.write_tag(0);
+                self.write_children(children);
+            }
+            Element::Alternative { ref children } => {
+                self.write_tag(1);
+                self.write_children(children);
+            }
+            Element::Permutation {
+                ref children,
+                all_required,
+            } => {
+                self.write_tag(2);
+                self.write_children(children);
+                self.write_bool(all_required);
+            }
+            Element::Repetition { ref child } => {
+                self.write_tag(3);
+                self.write_element(child);
+            }
+            Element::SeparatedList {
+                ref child,
+                ref separator,
+                ref last_separator,
+            } => {
+                self.write_tag(4);
+                self.write_element(child);
+                self.write_element(separator);
+                match *last_separator {
+                    Some(ref last) => {
+                        self.write_tag(1);
+                        self.write_element(last);
+                    }
+                    None => self.write_tag(0),
+                }
+            }
+            Element::Optional { ref child } => {
+                self.write_tag(5);
+                self.write_element(child);
+            }
+            Element::Capture {
+                ref name,
+                ref child,
+            } => {
+                self.write_tag(6);
+                self.write_str(name);
+                self.write_element(child);
+            }
+            Element::Word { ref text } => {
+                self.write_tag(7);
+                self.write_str(text);
+            }
+            Element::RuleRef { ref name } => {
+                self.write_tag(8);
+                self.write_str(name);
+            }
+            Element::List { ref name } => {
+                self.write_tag(9);
+                self.write_str(name);
+            }
+            Element::Import { ref name } => {
+                self.write_tag(10);
+                self.write_str(name);
+            }
+            Element::Dictation {
+                min_words,
+                max_words,
+                ref stop_words,
+                greedy,
+            } => {
+                self.write_tag(11);
+                self.write_option_usize(min_words);
+                self.write_option_usize(max_words);
+                self.write_usize(stop_words.len());
+                for w in stop_words.iter() {
+                    self.write_str(w);
+                }
+                self.write_bool(greedy);
+            }
+            Element::DictationWord { exclude_keywords } => {
+                self.write_tag(12);
+                self.write_bool(exclude_keywords);
+            }
+            Element::SpellingLetter => self.write_tag(13),
+        }
+    }
+}
+
+/// The output of compiling a command grammar: the binary grammar that
+/// is loaded into Dragon and the matcher for its results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompiledGrammar {
+    compiled: Vec<u8>,
+    matcher: Matcher,
+}
+
+impl CompiledGrammar {
+    pub fn compile(grammar: &Grammar) -> Result<Self> {
+        Ok(CompiledGrammar {
+            compiled: compile_command_grammar(grammar)?,
+            matcher: Matcher::try_new(grammar)?,
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.compiled
+    }
+
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    /// The contents of lists are not cached, so a grammar with lists
+    /// needs those of its `CommandGrammarControl`.
+    pub fn set_lists(&mut self, lists: ListContents) {
+        self.matcher.set_lists(lists);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    grammar: Grammar,
+    compiled: CompiledGrammar,
+}
+
+pub struct GrammarCache {
+    directory: PathBuf,
+    max_size: u64,
+}
+
+impl GrammarCache {
+    /// Creates the directory if it does not exist yet. Once the entries
+    /// take up more than `max_size` bytes, the oldest ones are removed.
+    pub fn new<P: Into<PathBuf>>(directory: P, max_size: u64) -> Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory).map_err(|e| io_error(&directory, e))?;
+
+        Ok(GrammarCache {
+            directory: directory,
+            max_size: max_size,
+        })
+    }
+
+    pub fn get_or_compile(&self, grammar: &Grammar) -> Result<CompiledGrammar> {
+        if let Some(compiled) = self.get(grammar)? {
+            return Ok(compiled);
+        }
+
+        let compiled = CompiledGrammar::compile(grammar)?;
+        self.insert(grammar, &compiled)?;
+
+        Ok(compiled)
+    }
+
+    pub fn get(&self, grammar: &Grammar) -> Result<Option<CompiledGrammar>> {
+        let path = self.entry_path(grammar);
+
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+
+        // a corrupt entry is treated as missing, it will be overwritten
+        let entry: CacheEntry = match serde_json::from_slice(&data) {
+            Ok(entry) => entry,
+            Err(_) => return Ok(None),
+        };
+
+        if entry.grammar != *grammar {
+            return Ok(None);
+        }
+
+        // failing to mark the entry as used only affects eviction
+        if let Ok(file) = fs::OpenOptions::new().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Ok(Some(entry.compiled))
+    }
+
+    pub fn insert(&self, grammar: &Grammar, compiled: &CompiledGrammar) -> Result<()> {
+        let path = self.entry_path(grammar);
+
+        let entry = CacheEntry {
+            grammar: grammar.clone(),
+            compiled: compiled.clone(),
+        };
+        let data = serde_json::to_vec(&entry).map_err(CacheError::Serialization)?;
+
+        // the entry is written under another name first, so a crash while
+        // writing it can not leave a truncated entry behind
+        let extension = format!("{}.{}", process::id(), TEMPORARY_EXTENSION);
+        let temporary = path.with_extension(extension);
+        fs::write(&temporary, data).map_err(|e| io_error(&temporary, e))?;
+        if let Err(e) = fs::rename(&temporary, &path) {
+            let _ = fs::remove_file(&temporary);
+            return Err(io_error(&path, e));
+        }
+
+        self.evict()
+    }
+
+    pub fn invalidate(&self, grammar: &Grammar) -> Result<()> {
+        let path = self.entry_path(grammar);
+
+        match fs::remove_file(&path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            other => other.map_err(|e| io_error(&path, e)),
+        }
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        for (path, _, _) in self.entries()? {
+            fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+        }
+
+        self.remove_stale_temporaries()
+    }
+
+    fn entry_path(&self, grammar: &Grammar) -> PathBuf {
+        let name = format!("{:016x}-{}.{}", fingerprint(grammar), VERSION, EXTENSION);
+        self.directory.join(name)
+    }
+
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+        self.files_with_extension(EXTENSION)
+    }
+
+    fn files_with_extension(&self, extension: &str) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let directory = &self.directory;
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(directory).map_err(|e| io_error(directory, e))? {
+            let entry = entry.map_err(|e| io_error(directory, e))?;
+            let path = entry.path();
+
+            if path.extension().map_or(true, |e| e != extension) {
+                continue;
+            }
+
+            let metadata = entry.metadata().map_err(|e| io_error(&path, e))?;
+            let modified = metadata.modified().map_err(|e| io_error(&path, e))?;
+            files.push((path, metadata.len(), modified));
+        }
+
+        Ok(files)
+    }
+
+    // temporary files that are recent may still be written by another
+    // process
+    fn remove_stale_temporaries(&self) -> Result<()> {
+        let now = SystemTime::now();
+
+        for (path, _, modified) in self.files_with_extension(TEMPORARY_EXTENSION)? {
+            let age = now.duration_since(modified).unwrap_or_default();
+            if age < STALE_TEMPORARY_AGE {
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                other => other.map_err(|e| io_error(&path, e))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // entries from other versions of the crate are removed first, then
+    // the least recently used entries
+    fn evict(&self) -> Result<()> {
+        self.remove_stale_temporaries()?;
+
+        let current_version = format!("-{}.{}", VERSION, EXTENSION);
+        let mut entries = self.entries()?;
+
+        entries.sort_by_key(|&(ref path, _, modified)| {
+            let current = path.to_string_lossy().ends_with(&current_version);
+            (current, modified)
+        });
+
+        let mut total: u64 = entries.iter().map(|&(_, size, _)| size).sum();
+
+        for (path, size, _) in entries {
+            let stale = !path.to_string_lossy().ends_with(&current_version);
+            if total <= self.max_size && !stale {
+                break;
+            }
+
+            fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+            total -= size;
+        }
+
+        Ok(())
+    }
+}
+
+fn io_error(path: &Path, cause: io::Error) -> CacheError {
+    CacheError::Io {
+        path: path.display().to_string(),
+        cause: cause,
+    }
+}
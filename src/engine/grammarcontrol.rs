@@ -1,6 +1,7 @@
 use crate::dragon::{RECEIVE_SDATA, SDATA, SRWORD};
 use crate::errors::*;
 use crate::interfaces::{IDgnSRGramSelect, ISRGramCFG, ISRGramCommon, ISRGramDictation};
+use crate::resultparser::ListContents;
 use byteorder::{LittleEndian, WriteBytesExt};
 use components::bstr::{BStr, BString};
 use components::comptr::ComPtr;
@@ -12,6 +13,7 @@ use std::slice;
 pub struct CommandGrammarControl {
     grammar_control: ComPtr<ISRGramCommon>,
     grammar_lists: ComPtr<ISRGramCFG>,
+    lists: ListContents,
 }
 
 pub fn create_command(grammar_control: ComPtr<ISRGramCommon>) -> Result<CommandGrammarControl> {
@@ -20,6 +22,7 @@ pub fn create_command(grammar_control: ComPtr<ISRGramCommon>) -> Result<CommandG
     Ok(CommandGrammarControl {
         grammar_control: grammar_control,
         grammar_lists: grammar_lists,
+        lists: ListContents::new(),
     })
 }
 
@@ -44,36 +47,47 @@ impl CommandGrammarControl {
         Ok(())
     }
 
+    /// The contents of the grammar's lists, which are kept up to date by
+    /// `list_append`, `list_remove` and `list_clear`. Pass them to the
+    /// matcher for the grammar's results with `MatcherOptions::lists`, or
+    /// with `CompiledGrammar::set_lists` for a cached grammar.
+    pub fn lists(&self) -> ListContents {
+        self.lists.clone()
+    }
+
     pub fn list_append(&self, name: &str, word: &str) -> Result<()> {
-        let name = BString::from(name);
+        let list = BString::from(name);
         let srword: SRWORD = word.into();
         let data = word_into_data(&srword);
 
-        let rc = unsafe { self.grammar_lists.list_append(name.as_ref(), data) };
+        let rc = unsafe { self.grammar_lists.list_append(list.as_ref(), data) };
 
         rc.result()?;
+        self.lists.append(name, word);
         Ok(())
     }
 
     pub fn list_remove(&self, name: &str, word: &str) -> Result<()> {
-        let name = BString::from(name);
+        let list = BString::from(name);
         let srword: SRWORD = word.into();
         let data = word_into_data(&srword);
 
-        let rc = unsafe { self.grammar_lists.list_remove(name.as_ref(), data) };
+        let rc = unsafe { self.grammar_lists.list_remove(list.as_ref(), data) };
 
         rc.result()?;
+        self.lists.remove(name, word);
         Ok(())
     }
 
     pub fn list_clear(&self, name: &str) -> Result<()> {
-        let name = BString::from(name);
+        let list = BString::from(name);
         let srword: SRWORD = "".into();
         let data = word_into_data(&srword);
 
-        let rc = unsafe { self.grammar_lists.list_set(name.as_ref(), data) };
+        let rc = unsafe { self.grammar_lists.list_set(list.as_ref(), data) };
 
         rc.result()?;
+        self.lists.clear(name);
         Ok(())
     }
 }
@@ -1,14 +1,17 @@
 use self::enginesink::{EngineSink, PauseCookie};
 use self::grammarsink::{GrammarSink, RawGrammarEvent};
+use crate::cache::CompiledGrammar;
 use crate::dragon::SRGRMFMT;
 use crate::errors::*;
 use crate::grammar::{Element, Grammar, Rule};
+use crate::grammarcompiler::verifier::verify_grammar;
+use crate::grammarcompiler::wordlists::{extract_word_lists, WordListOptions};
 use crate::grammarcompiler::{
     compile_command_grammar, compile_dictation_grammar, compile_select_grammar,
 };
 use crate::interfaces::{
     CLSID_DgnDictate, CLSID_DgnSite, IDgnSREngineControl, IDgnSREngineNotifySink, IDgnSRGramCommon,
-    ISRCentral, ISRGramCommon, ISRGramNotifySink, ISRSpeaker, IServiceProvider,
+    ISRCentral, ISRGramCommon, ISRGramNotifySink, ISRResGraph, ISRSpeaker, IServiceProvider,
 };
 use bitflags::bitflags;
 use components::comptr::ComPtr;
@@ -16,6 +19,7 @@ use components::{
     create_instance, raw_to_comptr, Cast, ComInterface, IUnknown, RawComPtr, CLSCTX_LOCAL_SERVER,
     GUID, HRESULT,
 };
+use log::error;
 use serde::{Deserialize, Serialize};
 use std::mem;
 use std::ptr;
@@ -32,8 +36,8 @@ pub use self::grammarcontrol::{
     CatchallGrammarControl, CommandGrammarControl, DictationGrammarControl, SelectGrammarControl,
 };
 pub use self::results::{
-    CatchallGrammarEvent, CommandGrammarEvent, DictationGrammarEvent, SelectGrammarEvent, WordInfo,
-    Words,
+    Alternatives, AlternativesGrammarEvent, CatchallGrammarEvent, CommandGrammarEvent,
+    DictationGrammarEvent, SelectGrammarEvent, WordInfo, Words,
 };
 
 bitflags! {
@@ -178,6 +182,10 @@ impl Engine {
     where
         F: Fn(RawGrammarEvent) + Sync + 'static,
     {
+        if cfg!(debug_assertions) {
+            verify_grammar(compiled)?;
+        }
+
         let mut raw_control = ptr::null_mut();
 
         let mut flags = GrammarFlags::SEND_PHRASE_START | GrammarFlags::SEND_PHRASE_FINISH;
@@ -249,7 +257,56 @@ impl Engine {
         F: Fn(CommandGrammarEvent) + Sync + 'static,
     {
         let compiled = compile_command_grammar(grammar)?;
+        self.command_grammar_load_bytes(&compiled, callback)
+    }
 
+    /// Loads a command grammar that has already been compiled, for
+    /// instance one that was retrieved from a `GrammarCache`. Since it may
+    /// have been read from disk, it is verified even in release builds.
+    pub fn command_grammar_load_compiled<F>(
+        &self,
+        compiled: &CompiledGrammar,
+        callback: F,
+    ) -> Result<CommandGrammarControl>
+    where
+        F: Fn(CommandGrammarEvent) + Sync + 'static,
+    {
+        verify_grammar(compiled.bytes())?;
+        self.command_grammar_load_bytes(compiled.bytes(), callback)
+    }
+
+    /// Loads a command grammar with large word alternatives turned into
+    /// lists, which are filled before the grammar is returned. Results
+    /// can be matched against the original grammar.
+    pub fn command_grammar_load_with_word_lists<F>(
+        &self,
+        grammar: &Grammar,
+        options: &WordListOptions,
+        callback: F,
+    ) -> Result<CommandGrammarControl>
+    where
+        F: Fn(CommandGrammarEvent) + Sync + 'static,
+    {
+        let converted = extract_word_lists(grammar, options);
+        let control = self.command_grammar_load(&converted.grammar, callback)?;
+
+        for list in converted.lists.iter() {
+            for word in list.words.iter() {
+                control.list_append(&list.name, word)?;
+            }
+        }
+
+        Ok(control)
+    }
+
+    fn command_grammar_load_bytes<F>(
+        &self,
+        compiled: &[u8],
+        callback: F,
+    ) -> Result<CommandGrammarControl>
+    where
+        F: Fn(CommandGrammarEvent) + Sync + 'static,
+    {
         let wrapped = move |e: RawGrammarEvent| {
             let new_event = e.map(|r| {
                 results::retrieve_words(&r.ptr.cast().unwrap(), 0)
@@ -258,7 +315,7 @@ impl Engine {
             });
             callback(new_event);
         };
-        let control = self.grammar_helper(SRGRMFMT::SRGRMFMT_CFG, &compiled, false, wrapped)?;
+        let control = self.grammar_helper(SRGRMFMT::SRGRMFMT_CFG, compiled, false, wrapped)?;
 
         grammarcontrol::create_command(control)
     }
@@ -266,13 +323,34 @@ impl Engine {
     pub fn dictation_grammar_load<F>(&self, callback: F) -> Result<DictationGrammarControl>
     where
         F: Fn(DictationGrammarEvent) + Sync + 'static,
+    {
+        self.dictation_grammar_load_with(results::retrieve_command_choices, callback)
+    }
+
+    /// Like `dictation_grammar_load`, but every recognition also comes
+    /// with its lattice of alternative words, which takes many more calls
+    /// into Dragon to retrieve.
+    pub fn dictation_grammar_load_with_lattice<F>(
+        &self,
+        callback: F,
+    ) -> Result<DictationGrammarControl>
+    where
+        F: Fn(AlternativesGrammarEvent) + Sync + 'static,
+    {
+        self.dictation_grammar_load_with(results::retrieve_alternatives, callback)
+    }
+
+    fn dictation_grammar_load_with<T, R, F>(
+        &self,
+        retrieve: R,
+        callback: F,
+    ) -> Result<DictationGrammarControl>
+    where
+        R: Fn(&ISRResGraph) -> Result<T> + Sync + 'static,
+        F: Fn(GrammarEvent<T>) + Sync + 'static,
     {
         let compiled = compile_dictation_grammar();
-        let wrapped = move |e: RawGrammarEvent| {
-            let new_event =
-                e.map(|r| results::retrieve_command_choices(&r.ptr.cast().unwrap()).unwrap());
-            callback(new_event);
-        };
+        let wrapped = move |e: RawGrammarEvent| callback(retrieve_results(e, &retrieve));
         let control =
             self.grammar_helper(SRGRMFMT::SRGRMFMT_DICTATION, &compiled, false, wrapped)?;
 
@@ -282,6 +360,30 @@ impl Engine {
     pub fn catchall_grammar_load<F>(&self, callback: F) -> Result<CatchallGrammarControl>
     where
         F: Fn(CatchallGrammarEvent) + Sync + 'static,
+    {
+        self.catchall_grammar_load_with(results::retrieve_command_choices, callback)
+    }
+
+    /// Like `catchall_grammar_load`, but every recognition also comes
+    /// with its lattice of alternative words.
+    pub fn catchall_grammar_load_with_lattice<F>(
+        &self,
+        callback: F,
+    ) -> Result<CatchallGrammarControl>
+    where
+        F: Fn(AlternativesGrammarEvent) + Sync + 'static,
+    {
+        self.catchall_grammar_load_with(results::retrieve_alternatives, callback)
+    }
+
+    fn catchall_grammar_load_with<T, R, F>(
+        &self,
+        retrieve: R,
+        callback: F,
+    ) -> Result<CatchallGrammarControl>
+    where
+        R: Fn(&ISRResGraph) -> Result<T> + Sync + 'static,
+        F: Fn(GrammarEvent<T>) + Sync + 'static,
     {
         let rule = Rule {
             name: "dummy".to_owned(),
@@ -294,11 +396,7 @@ impl Engine {
         let grammar = Grammar { rules: vec![rule] };
         let compiled = compile_command_grammar(&grammar)?;
 
-        let wrapped = move |e: RawGrammarEvent| {
-            let new_event =
-                e.map(|r| results::retrieve_command_choices(&r.ptr.cast().unwrap()).unwrap());
-            callback(new_event);
-        };
+        let wrapped = move |e: RawGrammarEvent| callback(retrieve_results(e, &retrieve));
         let control = self.grammar_helper(SRGRMFMT::SRGRMFMT_CFG, &compiled, true, wrapped)?;
 
         grammarcontrol::create_catchall(control)
@@ -319,6 +417,33 @@ impl Drop for EngineRegistration {
     }
 }
 
+// the callbacks are called by Dragon, so a failure to retrieve the
+// results is reported as a failed recognition rather than a panic
+fn retrieve_results<T, R>(event: RawGrammarEvent, retrieve: &R) -> GrammarEvent<T>
+where
+    R: Fn(&ISRResGraph) -> Result<T>,
+{
+    let recognition = match event {
+        GrammarEvent::PhraseFinish { result } => result,
+        GrammarEvent::PhraseRecognitionFailure => return GrammarEvent::PhraseRecognitionFailure,
+        GrammarEvent::PhraseStart => return GrammarEvent::PhraseStart,
+    };
+
+    let retrieved = recognition
+        .ptr
+        .cast()
+        .map_err(Error::from)
+        .and_then(|r: ComPtr<ISRResGraph>| retrieve(&r));
+
+    match retrieved {
+        Ok(result) => GrammarEvent::PhraseFinish { result: result },
+        Err(e) => {
+            error!("could not retrieve recognition results: {}", e);
+            GrammarEvent::PhraseRecognitionFailure
+        }
+    }
+}
+
 fn grammar_guid(grammar_dragon: &IDgnSRGramCommon) -> Result<GUID> {
     let mut guid: GUID = GUID {
         data1: 0,
@@ -2,8 +2,10 @@ use super::events::GrammarEvent;
 use crate::dragon::{SRRESWORDNODE, SRWORD, VOICEPARTOFSPEECH};
 use crate::errors::Result;
 use crate::interfaces::{IDgnSRResSelect, ISRResGraph};
+use crate::resultparser::{Lattice, LatticeNode};
 use components::{Cast, GUID};
 use serde::Serialize;
+use std::collections::HashMap;
 use std::mem;
 
 const VALUE_OUT_OF_RANGE: u32 = 0x8000_FFFF;
@@ -11,16 +13,25 @@ const NOT_A_SELECT_RESULT: u32 = 0x8004_1019;
 
 pub type DictationGrammarEvent = GrammarEvent<Vec<Words>>;
 pub type CatchallGrammarEvent = GrammarEvent<Vec<Words>>;
+pub type AlternativesGrammarEvent = GrammarEvent<Alternatives>;
 pub type CommandGrammarEvent = GrammarEvent<Words>;
 pub type Words = Vec<WordInfo>;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct WordInfo {
     pub text: String,
     pub start_time: u64,
     pub end_time: u64,
 }
 
+/// The alternative recognitions of an utterance, both as a list ordered
+/// from best to worst and as a lattice of alternative words.
+#[derive(Debug, Serialize)]
+pub struct Alternatives {
+    pub choices: Vec<Words>,
+    pub lattice: Lattice,
+}
+
 pub type Selection = (Words, u32, u32);
 pub type SelectGrammarEvent = GrammarEvent<Vec<Selection>>;
 
@@ -33,6 +44,13 @@ fn string_from_slice(s: &[u16]) -> String {
     )
 }
 
+pub fn retrieve_alternatives(results: &ISRResGraph) -> Result<Alternatives> {
+    Ok(Alternatives {
+        choices: retrieve_command_choices(results)?,
+        lattice: retrieve_lattice(results)?,
+    })
+}
+
 pub fn retrieve_command_choices(results: &ISRResGraph) -> Result<Vec<Words>> {
     let mut choices = Vec::new();
 
@@ -46,6 +64,109 @@ pub fn retrieve_command_choices(results: &ISRResGraph) -> Result<Vec<Words>> {
 }
 
 pub fn retrieve_words(results: &ISRResGraph, choice: u32) -> Result<Option<Words>> {
+    let path = match best_path(results, choice)? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let mut words = Vec::new();
+    for &number in path.iter() {
+        let (_, info) = word_node(results, number)?;
+        words.push(info);
+    }
+
+    Ok(Some(words))
+}
+
+/// Builds a lattice from the word nodes of the results. The alternatives
+/// of a node are the nodes reachable through its up and down alternate
+/// links, and they all follow the node before it.
+pub fn retrieve_lattice(results: &ISRResGraph) -> Result<Lattice> {
+    let first = match best_path(results, 0)? {
+        Some(ref path) if !path.is_empty() => path[0],
+        _ => return Ok(Lattice::default()),
+    };
+
+    let mut builder = LatticeBuilder {
+        results: results,
+        lattice: Lattice::default(),
+        indices: HashMap::new(),
+        pending: Vec::new(),
+    };
+
+    builder.lattice.starts = builder.alternatives(first)?;
+
+    while let Some((index, next)) = builder.pending.pop() {
+        // 0 is not a valid node number, it marks the end of the path
+        if next != 0 {
+            let next = builder.alternatives(next)?;
+            builder.lattice.nodes[index].next = next;
+        }
+    }
+
+    Ok(builder.lattice)
+}
+
+struct LatticeBuilder<'r> {
+    results: &'r ISRResGraph,
+    lattice: Lattice,
+    // node numbers to indices in the lattice
+    indices: HashMap<u32, usize>,
+    // nodes whose next nodes have not been added yet
+    pending: Vec<(usize, u32)>,
+}
+
+impl<'r> LatticeBuilder<'r> {
+    fn alternatives(&mut self, number: u32) -> Result<Vec<usize>> {
+        let (node, _) = word_node(self.results, number)?;
+        let mut alternatives = vec![self.add(number)?];
+
+        for &up in [true, false].iter() {
+            let mut link = if up {
+                node.dwUpAlternateWordNode
+            } else {
+                node.dwDownAlternateWordNode
+            };
+
+            while link != 0 && link != number {
+                let index = self.add(link)?;
+                if alternatives.contains(&index) {
+                    break;
+                }
+                alternatives.push(index);
+
+                let (alternate, _) = word_node(self.results, link)?;
+                link = if up {
+                    alternate.dwUpAlternateWordNode
+                } else {
+                    alternate.dwDownAlternateWordNode
+                };
+            }
+        }
+
+        Ok(alternatives)
+    }
+
+    fn add(&mut self, number: u32) -> Result<usize> {
+        if let Some(&index) = self.indices.get(&number) {
+            return Ok(index);
+        }
+
+        let (node, info) = word_node(self.results, number)?;
+        let index = self.lattice.nodes.len();
+        self.lattice.nodes.push(LatticeNode {
+            word: info,
+            score: node.dwWordScore,
+            next: Vec::new(),
+        });
+        self.indices.insert(number, index);
+        self.pending.push((index, node.dwNextWordNode));
+
+        Ok(index)
+    }
+}
+
+fn best_path(results: &ISRResGraph, choice: u32) -> Result<Option<Vec<u32>>> {
     type Path = [u32; 512];
     let mut path: Path = [0u32; 512];
     let mut actual_path_size: u32 = 0;
@@ -64,8 +185,12 @@ pub fn retrieve_words(results: &ISRResGraph, choice: u32) -> Result<Option<Words
     rc.result()?;
 
     // bytes to number of elements
-    let actual_path_size = actual_path_size / mem::size_of::<u32>() as u32;
+    let actual_path_size = actual_path_size as usize / mem::size_of::<u32>();
+
+    Ok(Some(path[..actual_path_size].to_vec()))
+}
 
+fn word_node(results: &ISRResGraph, number: u32) -> Result<(SRRESWORDNODE, WordInfo)> {
     let mut word_node: SRRESWORDNODE = SRRESWORDNODE {
         dwNextWordNode: 0,
         dwUpAlternateWordNode: 0,
@@ -90,29 +215,24 @@ pub fn retrieve_words(results: &ISRResGraph, choice: u32) -> Result<Option<Words
 
     let mut size_needed = 0u32;
 
-    let mut words = Vec::new();
-    for i in 0..actual_path_size {
-        let rc = unsafe {
-            results.get_word_node(
-                path[i as usize],
-                &mut word_node,
-                &mut word,
-                mem::size_of::<SRWORD>() as u32,
-                &mut size_needed,
-            )
-        };
-        rc.result()?;
-
-        let info = WordInfo {
-            text: string_from_slice(&word.buffer),
-            start_time: word_node.qwStartTime,
-            end_time: word_node.qwEndTime,
-        };
+    let rc = unsafe {
+        results.get_word_node(
+            number,
+            &mut word_node,
+            &mut word,
+            mem::size_of::<SRWORD>() as u32,
+            &mut size_needed,
+        )
+    };
+    rc.result()?;
 
-        words.push(info);
-    }
+    let info = WordInfo {
+        text: string_from_slice(&word.buffer),
+        start_time: word_node.qwStartTime,
+        end_time: word_node.qwEndTime,
+    };
 
-    Ok(Some(words))
+    Ok((word_node, info))
 }
 
 pub fn retrieve_selection_choices(
@@ -0,0 +1,322 @@
+//! Compiles grammars into OpenFST text format, so the same command
+//! grammars can be used with open-source recognizers such as Kaldi.
+//!
+//! The result is an acceptor over words (written as a transducer with
+//! identical input and output labels) along with its symbol table. Rule
+//! references are expanded in place, so recursive grammars are not
+//! supported. Elements that Dragon fills in itself are represented by
+//! a single placeholder arc, which a decoder is expected to replace:
+//!
+//! - `#nonterm:dictation` for dictation,
+//! - `#nonterm:dictation_word` for a single dictation word,
+//! - `#nonterm:spelling_letter` for a spelling letter,
+//! - `#nonterm:list:<name>` for a list,
+//! - `#nonterm:import:<name>` for other imported rules.
+//!
+//! Words, list names and import names can not contain whitespace, and
+//! words can not be `<eps>` or start with `#nonterm:`.
+
+use crate::backend::GrammarBackend;
+use crate::grammar::{Element, Grammar, Rule, MAX_PERMUTATION_CHILDREN};
+use failure::Fail;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Fail, Debug)]
+pub enum FstError {
+    #[fail(display = "unknown rule name in grammar definition: {}", name)]
+    UnknownRule { name: String },
+    #[fail(display = "recursive rule can not be compiled to an FST: {}", name)]
+    RecursiveRule { name: String },
+    #[fail(
+        display = "permutation with {} children in grammar definition (at most {} allowed)",
+        count, max
+    )]
+    PermutationTooLarge { count: usize, max: usize },
+    #[fail(display = "permutation without children in grammar definition")]
+    EmptyPermutation,
+    #[fail(display = "word or name can not be used as an FST symbol: {:?}", _0)]
+    InvalidSymbol(String),
+    #[fail(display = "grammar definition has no exported rules")]
+    NoExportedRules,
+}
+
+pub type Result<T> = ::std::result::Result<T, FstError>;
+
+pub const EPSILON: &str = "<eps>";
+
+const NONTERMINAL_PREFIX: &str = "#nonterm:";
+
+#[derive(Debug, Clone)]
+pub struct FstGrammar {
+    /// The FST in the text format read by `fstcompile`.
+    pub fst: String,
+    /// The symbol table in the format read by `fstcompile --isymbols`.
+    pub symbols: String,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FstBackend;
+
+impl GrammarBackend for FstBackend {
+    type Output = FstGrammar;
+    type Error = FstError;
+
+    fn compile(&self, grammar: &Grammar) -> Result<FstGrammar> {
+        compile_fst_grammar(grammar)
+    }
+}
+
+pub fn compile_fst_grammar(grammar: &Grammar) -> Result<FstGrammar> {
+    if !grammar.rules.iter().any(|r| r.exported) {
+        return Err(FstError::NoExportedRules);
+    }
+
+    let mut compiler = FstCompiler::new(grammar);
+
+    let start = compiler.new_state();
+    let end = compiler.new_state();
+
+    for r in grammar.rules.iter().filter(|r| r.exported) {
+        let mut active = vec![&r.name as &str];
+        compiler.compile_element(&r.definition, start, end, &mut active)?;
+    }
+
+    Ok(compiler.done(end))
+}
+
+type State = u32;
+
+struct FstCompiler<'a> {
+    rules: HashMap<&'a str, &'a Rule>,
+    symbols: Vec<String>,
+    symbol_ids: HashMap<String, u32>,
+    arcs: Vec<(State, State, u32)>,
+    state_count: State,
+}
+
+impl<'a> FstCompiler<'a> {
+    fn new(grammar: &'a Grammar) -> Self {
+        let mut compiler = FstCompiler {
+            rules: grammar.rules.iter().map(|r| (&r.name as &str, r)).collect(),
+            symbols: Vec::new(),
+            symbol_ids: HashMap::new(),
+            arcs: Vec::new(),
+            state_count: 0,
+        };
+
+        // OpenFST requires epsilon to be 0
+        compiler.symbol(EPSILON);
+        compiler
+    }
+
+    fn new_state(&mut self) -> State {
+        let state = self.state_count;
+        self.state_count += 1;
+        state
+    }
+
+    fn symbol(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.symbol_ids.get(name) {
+            return id;
+        }
+
+        let id = self.symbols.len() as u32;
+        self.symbols.push(name.to_owned());
+        self.symbol_ids.insert(name.to_owned(), id);
+        id
+    }
+
+    // symbols are separated by whitespace in both the FST and the symbol
+    // table, and epsilon has a meaning of its own
+    fn arc(&mut self, from: State, to: State, symbol: &str) -> Result<()> {
+        if symbol.is_empty() || symbol == EPSILON || symbol.contains(char::is_whitespace) {
+            return Err(FstError::InvalidSymbol(symbol.to_owned()));
+        }
+
+        let id = self.symbol(symbol);
+        self.arcs.push((from, to, id));
+        Ok(())
+    }
+
+    fn epsilon(&mut self, from: State, to: State) {
+        let id = self.symbol(EPSILON);
+        self.arcs.push((from, to, id));
+    }
+
+    fn done(mut self, end: State) -> FstGrammar {
+        // the first line of the text format determines the start state
+        self.arcs.sort_by_key(|&(from, _, _)| from);
+
+        let mut fst = String::new();
+        for &(from, to, symbol) in self.arcs.iter() {
+            let label = &self.symbols[symbol as usize];
+            writeln!(fst, "{} {} {} {}", from, to, label, label).unwrap();
+        }
+        writeln!(fst, "{}", end).unwrap();
+
+        let mut symbols = String::new();
+        for (id, s) in self.symbols.iter().enumerate() {
+            writeln!(symbols, "{} {}", s, id).unwrap();
+        }
+
+        FstGrammar {
+            fst: fst,
+            symbols: symbols,
+        }
+    }
+
+    fn compile_element(
+        &mut self,
+        element: &'a Element,
+        from: State,
+        to: State,
+        active: &mut Vec<&'a str>,
+    ) -> Result<()> {
+        match *element {
+            Element::Sequence { ref children } => {
+                let mut current = from;
+                for (i, c) in children.iter().enumerate() {
+                    let next = if i + 1 == children.len() {
+                        to
+                    } else {
+                        self.new_state()
+                    };
+                    self.compile_element(c, current, next, active)?;
+                    current = next;
+                }
+
+                if children.is_empty() {
+                    self.epsilon(from, to);
+                }
+            }
+            Element::Alternative { ref children } => {
+                for c in children.iter() {
+                    self.compile_element(c, from, to, active)?;
+                }
+            }
+            Element::Permutation {
+                ref children,
+                all_required,
+            } => {
+                if children.is_empty() {
+                    return Err(FstError::EmptyPermutation);
+                }
+
+                if children.len() > MAX_PERMUTATION_CHILDREN {
+                    return Err(FstError::PermutationTooLarge {
+                        count: children.len(),
+                        max: MAX_PERMUTATION_CHILDREN,
+                    });
+                }
+
+                let remaining = (0..children.len()).collect::<Vec<_>>();
+                self.compile_permutation(children, &remaining, all_required, from, to, active)?;
+            }
+            Element::Repetition { ref child } => {
+                let loop_start = self.new_state();
+                let loop_end = self.new_state();
+
+                self.epsilon(from, loop_start);
+                self.compile_element(child, loop_start, loop_end, active)?;
+                self.epsilon(loop_end, loop_start);
+                self.epsilon(loop_end, to);
+            }
+            Element::SeparatedList {
+                ref child,
+                ref separator,
+                ref last_separator,
+            } => {
+                let item_end = self.new_state();
+                let separator_end = self.new_state();
+
+                self.compile_element(child, from, item_end, active)?;
+                self.compile_element(separator, item_end, separator_end, active)?;
+                self.compile_element(child, separator_end, item_end, active)?;
+                self.epsilon(item_end, to);
+
+                if let Some(ref last) = *last_separator {
+                    let last_end = self.new_state();
+                    self.compile_element(last, item_end, last_end, active)?;
+                    self.compile_element(child, last_end, to, active)?;
+                }
+            }
+            Element::Optional { ref child } => {
+                self.compile_element(child, from, to, active)?;
+                self.epsilon(from, to);
+            }
+            Element::Capture { ref child, .. } => {
+                self.compile_element(child, from, to, active)?;
+            }
+            Element::Word { ref text } => {
+                // placeholders can not be confused with words
+                if text.starts_with(NONTERMINAL_PREFIX) {
+                    return Err(FstError::InvalidSymbol(text.clone()));
+                }
+
+                self.arc(from, to, text)?;
+            }
+            Element::RuleRef { ref name } => {
+                let name: &'a str = name;
+                let rule = *self.rules.get(name).ok_or_else(|| FstError::UnknownRule {
+                    name: name.to_owned(),
+                })?;
+
+                if active.contains(&name) {
+                    return Err(FstError::RecursiveRule {
+                        name: name.to_owned(),
+                    });
+                }
+
+                active.push(name);
+                self.compile_element(&rule.definition, from, to, active)?;
+                active.pop();
+            }
+            Element::List { ref name } => {
+                self.arc(from, to, &format!("{}list:{}", NONTERMINAL_PREFIX, name))?;
+            }
+            Element::Import { ref name } => {
+                self.arc(from, to, &format!("{}import:{}", NONTERMINAL_PREFIX, name))?;
+            }
+            Element::Dictation { .. } => {
+                self.arc(from, to, &format!("{}dictation", NONTERMINAL_PREFIX))?;
+            }
+            Element::DictationWord { .. } => {
+                self.arc(from, to, &format!("{}dictation_word", NONTERMINAL_PREFIX))?;
+            }
+            Element::SpellingLetter => {
+                self.arc(from, to, &format!("{}spelling_letter", NONTERMINAL_PREFIX))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile_permutation(
+        &mut self,
+        children: &'a [Element],
+        remaining: &[usize],
+        all_required: bool,
+        from: State,
+        to: State,
+        active: &mut Vec<&'a str>,
+    ) -> Result<()> {
+        for (i, &first) in remaining.iter().enumerate() {
+            let mut rest = remaining.to_vec();
+            rest.remove(i);
+
+            let middle = self.new_state();
+            self.compile_element(&children[first], from, middle, active)?;
+
+            if rest.is_empty() || !all_required {
+                self.epsilon(middle, to);
+            }
+
+            if !rest.is_empty() {
+                self.compile_permutation(children, &rest, all_required, middle, to, active)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,29 +1,78 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The largest number of children a permutation can have. Dragon and
+/// OpenFST have no notion of permutations, so they are expanded into
+/// alternatives over all orderings, which grows factorially.
+pub const MAX_PERMUTATION_CHILDREN: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Grammar {
     pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Rule {
     pub name: String,
     pub exported: bool,
     pub definition: Element,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Element {
     Sequence { children: Vec<Element> },
     Alternative { children: Vec<Element> },
+    /// Children in any order, each at most once. At least one child
+    /// must be present, or all of them if `all_required` is set. There
+    /// must be between 1 and `MAX_PERMUTATION_CHILDREN` children.
+    Permutation {
+        children: Vec<Element>,
+        #[serde(default)]
+        all_required: bool,
+    },
     Repetition { child: Box<Element> },
+    /// One or more occurrences of `child` separated by `separator`,
+    /// where the last two occurrences may instead be separated by
+    /// `last_separator`. Captures inside the separators are dropped.
+    SeparatedList {
+        child: Box<Element>,
+        separator: Box<Element>,
+        #[serde(default)]
+        last_separator: Option<Box<Element>>,
+    },
     Optional { child: Box<Element> },
     Capture { name: String, child: Box<Element> },
     Word { text: String },
     RuleRef { name: String },
     List { name: String },
-    Dictation,
-    DictationWord,
+    /// A rule that is not defined in this grammar, such as one of
+    /// Dragon's built-in rules or a rule exported by another grammar.
+    Import { name: String },
+    /// Free-form dictation. Dragon itself does not enforce any of the
+    /// options, they only affect how the result is parsed.
+    Dictation {
+        /// Defaults to one word. The matcher rejects limits of more than
+        /// 100 words, and a minimum above the maximum.
+        #[serde(default)]
+        min_words: Option<usize>,
+        #[serde(default)]
+        max_words: Option<usize>,
+        /// Words that can not be part of the dictation, so they end it.
+        #[serde(default)]
+        stop_words: Vec<String>,
+        /// Whether to match as many words as possible instead of as few
+        /// as possible.
+        #[serde(default)]
+        greedy: bool,
+    },
+    /// A single word of dictation.
+    DictationWord {
+        /// Whether the word can not be one of the words of the grammar,
+        /// so a misrecognized command word is not taken as dictation. Like
+        /// the options of `Dictation`, this only affects parsing.
+        #[serde(default)]
+        exclude_keywords: bool,
+    },
+    /// A single letter, as recognized by Dragon's `dgnletters` rule.
     SpellingLetter,
 }
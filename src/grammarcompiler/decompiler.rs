@@ -0,0 +1,479 @@
+//! Parsing of the binary grammar format produced by this module, which
+//! can be turned back into a `Grammar`. Rule names of rules that are not
+//! exported and captures are not part of the binary format, so rules
+//! that are not exported get generated names and the resulting grammar
+//! contains no captures.
+//!
+//! Rules are defined before the rules that refer to them, since that is
+//! the only order `compile_command_grammar` accepts. The rules generated
+//! for separated lists have higher IDs than the rules using them, so the
+//! rules are not in the order of their IDs.
+
+use super::errors::DecompileError;
+use super::ruletoken::{NestedPosition, NestedType, RuleId, RuleToken};
+use super::{ChunkType, ImportedRule, COMMAND_GRAMMAR};
+use crate::grammar::{Element, Grammar, Rule};
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+pub type Result<T> = ::std::result::Result<T, DecompileError>;
+
+const TOKEN_SIZE: usize = 8;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Chunk<'a> {
+    pub chunk_type: u32,
+    /// Offset of the chunk data from the start of the binary grammar.
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    pub fn known_type(&self) -> Option<ChunkType> {
+        ChunkType::from_u32(self.chunk_type)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Binary<'a> {
+    pub grammar_type: u32,
+    pub flags: u32,
+    pub chunks: Vec<Chunk<'a>>,
+}
+
+impl<'a> Binary<'a> {
+    pub fn chunk(&self, chunk_type: ChunkType) -> Option<&Chunk<'a>> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type == chunk_type as u32)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry<'a> {
+    pub id: u32,
+    /// Offset of the entry data from the start of the chunk.
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+// lengths are read from the data, so offsets computed from them can
+// overflow on 32-bit targets
+fn add(offset: usize, length: usize, base: usize) -> Result<usize> {
+    offset
+        .checked_add(length)
+        .ok_or(DecompileError::Truncated {
+            offset: base.saturating_add(offset),
+        })
+}
+
+fn read_u32(data: &[u8], offset: usize, base: usize) -> Result<u32> {
+    let end = add(offset, mem::size_of::<u32>(), base)?;
+    data.get(offset..end)
+        .map(LittleEndian::read_u32)
+        .ok_or(DecompileError::Truncated {
+            offset: base.saturating_add(offset),
+        })
+}
+
+pub fn parse_binary(data: &[u8]) -> Result<Binary> {
+    let grammar_type = read_u32(data, 0, 0)?;
+    let flags = read_u32(data, 4, 0)?;
+
+    let mut chunks = Vec::new();
+    let mut offset = 8;
+    while offset < data.len() {
+        let chunk_type = read_u32(data, offset, 0)?;
+        let length = read_u32(data, add(offset, 4, 0)?, 0)? as usize;
+        let start = add(offset, 8, 0)?;
+        let end = add(start, length, 0)?;
+
+        let chunk_data = data
+            .get(start..end)
+            .ok_or(DecompileError::Truncated { offset: start })?;
+
+        chunks.push(Chunk {
+            chunk_type: chunk_type,
+            offset: start,
+            data: chunk_data,
+        });
+
+        offset = end;
+    }
+
+    Ok(Binary {
+        grammar_type: grammar_type,
+        flags: flags,
+        chunks: chunks,
+    })
+}
+
+/// Splits a chunk into its entries, each of which starts with its total
+/// length and an ID.
+pub fn parse_entries<'a>(chunk: &Chunk<'a>) -> Result<Vec<Entry<'a>>> {
+    let data = chunk.data;
+    let mut entries = Vec::new();
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let total_length = read_u32(data, offset, chunk.offset)? as usize;
+        let id = read_u32(data, add(offset, 4, chunk.offset)?, chunk.offset)?;
+
+        let end = add(offset, total_length, chunk.offset)?;
+        if total_length < 8 || end > data.len() {
+            return Err(DecompileError::Truncated {
+                offset: chunk.offset.saturating_add(offset),
+            });
+        }
+
+        entries.push(Entry {
+            id: id,
+            offset: offset + 8,
+            data: &data[offset + 8..end],
+        });
+
+        offset = end;
+    }
+
+    Ok(entries)
+}
+
+/// Decodes a null-terminated UTF-16 string from an entry of an ID chunk.
+pub fn decode_name(data: &[u8]) -> String {
+    let units = data
+        .chunks(2)
+        .filter(|c| c.len() == 2)
+        .map(LittleEndian::read_u16)
+        .take_while(|&u| u != 0)
+        .collect::<Vec<_>>();
+
+    String::from_utf16_lossy(&units)
+}
+
+pub fn parse_id_chunk(chunk: &Chunk) -> Result<Vec<(u32, String)>> {
+    let entries = parse_entries(chunk)?;
+    Ok(entries
+        .iter()
+        .map(|e| (e.id, decode_name(e.data)))
+        .collect())
+}
+
+pub fn parse_rule_tokens(rule: RuleId, data: &[u8]) -> Result<Vec<RuleToken>> {
+    let mut tokens = Vec::new();
+
+    for raw in data.chunks(TOKEN_SIZE) {
+        if raw.len() != TOKEN_SIZE {
+            return Err(DecompileError::Unbalanced { rule: rule });
+        }
+
+        // the second u16 is the probability, which we do not use
+        let kind = LittleEndian::read_u16(&raw[0..2]);
+        let value = LittleEndian::read_u32(&raw[4..8]);
+
+        let token = RuleToken::from_raw(kind, value).ok_or(DecompileError::UnknownToken {
+            rule: rule,
+            kind: kind,
+            value: value,
+        })?;
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+pub fn parse_rule_chunk(chunk: &Chunk) -> Result<Vec<(RuleId, Vec<RuleToken>)>> {
+    let entries = parse_entries(chunk)?;
+    entries
+        .iter()
+        .map(|e| Ok((e.id, parse_rule_tokens(e.id, e.data)?)))
+        .collect()
+}
+
+pub fn decompile_grammar(data: &[u8]) -> Result<Grammar> {
+    let binary = parse_binary(data)?;
+    if binary.grammar_type != COMMAND_GRAMMAR {
+        return Err(DecompileError::NotCommandGrammar(binary.grammar_type));
+    }
+
+    let id_table = |chunk_type| -> Result<HashMap<u32, String>> {
+        match binary.chunk(chunk_type) {
+            Some(chunk) => Ok(parse_id_chunk(chunk)?.into_iter().collect()),
+            None => Ok(HashMap::new()),
+        }
+    };
+
+    let exports = id_table(ChunkType::Exports)?;
+    let imports = id_table(ChunkType::Imports)?;
+    let lists = id_table(ChunkType::Lists)?;
+    let words = id_table(ChunkType::Words)?;
+
+    let mut rules = match binary.chunk(ChunkType::Rules) {
+        Some(chunk) => parse_rule_chunk(chunk)?,
+        None => Vec::new(),
+    };
+    rules.sort_by_key(|&(id, _)| id);
+
+    let mut used_names = exports.values().cloned().collect::<HashSet<_>>();
+    let mut rule_names = HashMap::new();
+    for &(id, _) in rules.iter() {
+        let name = if let Some(name) = exports.get(&id) {
+            name.clone()
+        } else {
+            let mut name = format!("rule_{}", id);
+            while used_names.contains(&name) {
+                name.push('_');
+            }
+            used_names.insert(name.clone());
+            name
+        };
+
+        rule_names.insert(id, name);
+    }
+
+    let decompiler = Decompiler {
+        imports: &imports,
+        lists: &lists,
+        words: &words,
+        rule_names: &rule_names,
+    };
+
+    let mut result = Vec::new();
+    for (id, tokens) in order_callees_first(rules) {
+        result.push(Rule {
+            name: rule_names[&id].clone(),
+            exported: exports.contains_key(&id),
+            definition: decompiler.decompile_rule(id, &tokens)?,
+        });
+    }
+
+    Ok(Grammar { rules: result })
+}
+
+// a depth-first search from every rule in the order of their IDs, which
+// keeps that order wherever the references allow it. References that
+// form a cycle can not be ordered, and the compiler rejects them anyway.
+fn order_callees_first(rules: Vec<(RuleId, Vec<RuleToken>)>) -> Vec<(RuleId, Vec<RuleToken>)> {
+    let indices = rules
+        .iter()
+        .enumerate()
+        .map(|(index, &(id, _))| (id, index))
+        .collect::<HashMap<_, _>>();
+
+    let mut visited = vec![false; rules.len()];
+    let mut order = Vec::with_capacity(rules.len());
+
+    for root in 0..rules.len() {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+
+        // rules along with the position of the next token to look at
+        let mut stack = vec![(root, 0)];
+        while let Some(&mut (index, ref mut position)) = stack.last_mut() {
+            let tokens = &rules[index].1;
+            let callee = tokens[*position..].iter().position(|t| match *t {
+                RuleToken::Rule(id) => indices.get(&id).map_or(false, |&i| !visited[i]),
+                _ => false,
+            });
+
+            match callee {
+                Some(offset) => {
+                    let token_index = *position + offset;
+                    *position = token_index + 1;
+
+                    if let RuleToken::Rule(id) = tokens[token_index] {
+                        let callee = indices[&id];
+                        visited[callee] = true;
+                        stack.push((callee, 0));
+                    }
+                }
+                None => {
+                    order.push(index);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    let mut rules = rules.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .filter_map(|index| rules[index].take())
+        .collect()
+}
+
+struct Decompiler<'a> {
+    imports: &'a HashMap<u32, String>,
+    lists: &'a HashMap<u32, String>,
+    words: &'a HashMap<u32, String>,
+    rule_names: &'a HashMap<u32, String>,
+}
+
+fn single(mut children: Vec<Element>) -> Element {
+    if children.len() == 1 {
+        children.pop().unwrap()
+    } else {
+        Element::Sequence { children: children }
+    }
+}
+
+impl<'a> Decompiler<'a> {
+    fn decompile_rule(&self, rule: RuleId, tokens: &[RuleToken]) -> Result<Element> {
+        let mut stack: Vec<(NestedType, Vec<Element>)> = Vec::new();
+        let mut top = Vec::new();
+
+        for t in tokens.iter() {
+            let element = match *t {
+                RuleToken::Nested(NestedPosition::Start, ty) => {
+                    stack.push((ty, Vec::new()));
+                    continue;
+                }
+                RuleToken::Nested(NestedPosition::End, ty) => {
+                    let (open, children) = stack
+                        .pop()
+                        .ok_or(DecompileError::Unbalanced { rule: rule })?;
+
+                    if open != ty {
+                        return Err(DecompileError::Unbalanced { rule: rule });
+                    }
+
+                    match ty {
+                        NestedType::Sequence => Element::Sequence { children: children },
+                        NestedType::Alternative => Element::Alternative { children: children },
+                        NestedType::Repetition => Element::Repetition {
+                            child: Box::new(single(children)),
+                        },
+                        NestedType::Optional => Element::Optional {
+                            child: Box::new(single(children)),
+                        },
+                    }
+                }
+                RuleToken::Word(id) => Element::Word {
+                    text: self
+                        .words
+                        .get(&id)
+                        .ok_or(DecompileError::UnknownWord { rule: rule, id: id })?
+                        .clone(),
+                },
+                RuleToken::List(id) => Element::List {
+                    name: self
+                        .lists
+                        .get(&id)
+                        .ok_or(DecompileError::UnknownList { rule: rule, id: id })?
+                        .clone(),
+                },
+                RuleToken::Rule(id) => self.decompile_rule_reference(rule, id)?,
+            };
+
+            match stack.last_mut() {
+                Some(&mut (_, ref mut children)) => children.push(element),
+                None => top.push(element),
+            }
+        }
+
+        if !stack.is_empty() {
+            return Err(DecompileError::Unbalanced { rule: rule });
+        }
+
+        Ok(single(top))
+    }
+
+    fn decompile_rule_reference(&self, rule: RuleId, id: RuleId) -> Result<Element> {
+        if let Some(name) = self.rule_names.get(&id) {
+            return Ok(Element::RuleRef { name: name.clone() });
+        }
+
+        let name = self
+            .imports
+            .get(&id)
+            .ok_or(DecompileError::UnknownRule { rule: rule, id: id })?;
+
+        let element = match ImportedRule::from_name(name) {
+            Some(ImportedRule::Dictation) => Element::Dictation {
+                min_words: None,
+                max_words: None,
+                stop_words: Vec::new(),
+                greedy: false,
+            },
+            Some(ImportedRule::DictationWord) => Element::DictationWord {
+                exclude_keywords: false,
+            },
+            Some(ImportedRule::SpellingLetter) => Element::SpellingLetter,
+            None => Element::Import { name: name.clone() },
+        };
+
+        Ok(element)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompile_grammar;
+    use crate::grammar::{Element, Grammar, Rule};
+    use crate::grammarcompiler::compile_command_grammar;
+
+    fn word(text: &str) -> Element {
+        Element::Word {
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let number = Rule {
+            name: "number".to_owned(),
+            exported: false,
+            definition: Element::Alternative {
+                children: vec![word("one"), word("two")],
+            },
+        };
+
+        // the item of the separated list becomes a generated rule, which
+        // has a higher ID than the rule using it
+        let command = Rule {
+            name: "command".to_owned(),
+            exported: true,
+            definition: Element::Sequence {
+                children: vec![
+                    word("go"),
+                    Element::SeparatedList {
+                        child: Box::new(Element::Sequence {
+                            children: vec![
+                                Element::RuleRef {
+                                    name: "number".to_owned(),
+                                },
+                                word("steps"),
+                            ],
+                        }),
+                        separator: Box::new(word("and")),
+                        last_separator: None,
+                    },
+                    Element::List {
+                        name: "direction".to_owned(),
+                    },
+                    Element::Dictation {
+                        min_words: None,
+                        max_words: None,
+                        stop_words: Vec::new(),
+                        greedy: false,
+                    },
+                ],
+            },
+        };
+
+        let grammar = Grammar {
+            rules: vec![number, command],
+        };
+
+        let compiled = compile_command_grammar(&grammar).unwrap();
+        let decompiled = decompile_grammar(&compiled).unwrap();
+        let recompiled = compile_command_grammar(&decompiled).unwrap();
+
+        // the generated rules get new IDs, but after that the binary does
+        // not change anymore
+        let again = compile_command_grammar(&decompile_grammar(&recompiled).unwrap()).unwrap();
+        assert_eq!(again, recompiled);
+    }
+}
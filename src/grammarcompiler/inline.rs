@@ -0,0 +1,254 @@
+use crate::grammar::{Element, Grammar, Rule};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone)]
+pub struct InlineOptions {
+    /// Rules with at most this many elements are inlined.
+    pub max_size: usize,
+    /// Inline rules that are referenced exactly once, regardless of
+    /// their size.
+    pub single_use: bool,
+    /// Rules which are always inlined (unless they are exported).
+    pub always: HashSet<String>,
+    /// Rules which are never inlined.
+    pub never: HashSet<String>,
+}
+
+impl Default for InlineOptions {
+    fn default() -> Self {
+        InlineOptions {
+            max_size: 8,
+            single_use: true,
+            always: HashSet::new(),
+            never: HashSet::new(),
+        }
+    }
+}
+
+/// Substitutes small non-exported rules, and rules that are only used
+/// once, into the rules that reference them. Every rule reference costs
+/// a separate rule in the grammar that is handed to Dragon, and a call
+/// and return in the matcher. Captures are not affected by rule
+/// boundaries, so the matcher produces the same captures for the
+/// inlined grammar.
+pub fn inline_rules(grammar: &Grammar, options: &InlineOptions) -> Grammar {
+    let mut rules = HashMap::new();
+    for r in grammar.rules.iter() {
+        // leave reporting duplicates to the grammar compiler
+        if rules.insert(&r.name as &str, r).is_some() {
+            return grammar.clone();
+        }
+    }
+
+    let inliner = Inliner::new(rules, options);
+    inliner.inline_grammar(grammar)
+}
+
+fn element_size(element: &Element) -> usize {
+    match *element {
+        Element::Sequence { ref children }
+        | Element::Alternative { ref children }
+        | Element::Permutation { ref children, .. } => {
+            1 + children.iter().map(element_size).sum::<usize>()
+        }
+        Element::Repetition { ref child }
+        | Element::Optional { ref child }
+        | Element::Capture { ref child, .. } => 1 + element_size(child),
+        Element::SeparatedList {
+            ref child,
+            ref separator,
+            ref last_separator,
+        } => {
+            1 + element_size(child)
+                + element_size(separator)
+                + last_separator.as_ref().map_or(0, |l| element_size(l))
+        }
+        Element::Word { .. }
+        | Element::RuleRef { .. }
+        | Element::List { .. }
+        | Element::Import { .. }
+        | Element::Dictation { .. }
+        | Element::DictationWord { .. }
+        | Element::SpellingLetter => 1,
+    }
+}
+
+fn count_references<'a>(element: &'a Element, counts: &mut HashMap<&'a str, usize>) {
+    match *element {
+        Element::Sequence { ref children }
+        | Element::Alternative { ref children }
+        | Element::Permutation { ref children, .. } => {
+            for c in children.iter() {
+                count_references(c, counts);
+            }
+        }
+        Element::Repetition { ref child }
+        | Element::Optional { ref child }
+        | Element::Capture { ref child, .. } => count_references(child, counts),
+        Element::SeparatedList {
+            ref child,
+            ref separator,
+            ref last_separator,
+        } => {
+            count_references(child, counts);
+            count_references(separator, counts);
+            if let Some(ref last) = *last_separator {
+                count_references(last, counts);
+            }
+        }
+        Element::RuleRef { ref name } => {
+            *counts.entry(name).or_insert(0) += 1;
+        }
+        Element::Word { .. }
+        | Element::List { .. }
+        | Element::Import { .. }
+        | Element::Dictation { .. }
+        | Element::DictationWord { .. }
+        | Element::SpellingLetter => {}
+    }
+}
+
+struct Inliner<'a> {
+    rules: HashMap<&'a str, &'a Rule>,
+    candidates: HashSet<&'a str>,
+}
+
+impl<'a> Inliner<'a> {
+    fn new(rules: HashMap<&'a str, &'a Rule>, options: &InlineOptions) -> Self {
+        let mut counts = HashMap::new();
+        for r in rules.values() {
+            count_references(&r.definition, &mut counts);
+        }
+
+        let candidates = rules
+            .values()
+            .filter(|r| !r.exported && !options.never.contains(&r.name))
+            .filter(|r| {
+                options.always.contains(&r.name)
+                    || element_size(&r.definition) <= options.max_size
+                    || (options.single_use && counts.get(&r.name as &str) == Some(&1))
+            })
+            .map(|r| &r.name as &str)
+            .collect();
+
+        Inliner {
+            rules: rules,
+            candidates: candidates,
+        }
+    }
+
+    fn inline_grammar(&self, grammar: &'a Grammar) -> Grammar {
+        let inlined = grammar
+            .rules
+            .iter()
+            .map(|r| {
+                let mut active = vec![&r.name as &str];
+                Rule {
+                    name: r.name.clone(),
+                    exported: r.exported,
+                    definition: self.inline_element(&r.definition, &mut active),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // candidates can still be referenced if they are recursive, so
+        // only drop the ones that are no longer reachable
+        let mut keep = inlined
+            .iter()
+            .filter(|r| !self.candidates.contains(&r.name as &str))
+            .map(|r| r.name.clone())
+            .collect::<HashSet<_>>();
+
+        loop {
+            let mut counts = HashMap::new();
+            for r in inlined.iter().filter(|r| keep.contains(&r.name)) {
+                count_references(&r.definition, &mut counts);
+            }
+
+            let before = keep.len();
+            keep.extend(counts.keys().map(|&n| n.to_owned()));
+
+            if keep.len() == before {
+                break;
+            }
+        }
+
+        Grammar {
+            rules: inlined
+                .into_iter()
+                .filter(|r| keep.contains(&r.name))
+                .collect(),
+        }
+    }
+
+    fn inline_element(&self, element: &'a Element, active: &mut Vec<&'a str>) -> Element {
+        match *element {
+            Element::Sequence { ref children } => Element::Sequence {
+                children: children
+                    .iter()
+                    .map(|c| self.inline_element(c, active))
+                    .collect(),
+            },
+            Element::Alternative { ref children } => Element::Alternative {
+                children: children
+                    .iter()
+                    .map(|c| self.inline_element(c, active))
+                    .collect(),
+            },
+            Element::Permutation {
+                ref children,
+                all_required,
+            } => Element::Permutation {
+                children: children
+                    .iter()
+                    .map(|c| self.inline_element(c, active))
+                    .collect(),
+                all_required: all_required,
+            },
+            Element::Repetition { ref child } => Element::Repetition {
+                child: Box::new(self.inline_element(child, active)),
+            },
+            Element::SeparatedList {
+                ref child,
+                ref separator,
+                ref last_separator,
+            } => Element::SeparatedList {
+                child: Box::new(self.inline_element(child, active)),
+                separator: Box::new(self.inline_element(separator, active)),
+                last_separator: last_separator
+                    .as_ref()
+                    .map(|l| Box::new(self.inline_element(l, active))),
+            },
+            Element::Optional { ref child } => Element::Optional {
+                child: Box::new(self.inline_element(child, active)),
+            },
+            Element::Capture {
+                ref name,
+                ref child,
+            } => Element::Capture {
+                name: name.clone(),
+                child: Box::new(self.inline_element(child, active)),
+            },
+            Element::RuleRef { ref name } => {
+                let name: &'a str = name;
+                let inlinable = self.candidates.contains(name) && !active.contains(&name);
+
+                match self.rules.get(name) {
+                    Some(rule) if inlinable => {
+                        active.push(name);
+                        let result = self.inline_element(&rule.definition, active);
+                        active.pop();
+                        result
+                    }
+                    _ => element.clone(),
+                }
+            }
+            Element::Word { .. }
+            | Element::List { .. }
+            | Element::Import { .. }
+            | Element::Dictation { .. }
+            | Element::DictationWord { .. }
+            | Element::SpellingLetter => element.clone(),
+        }
+    }
+}
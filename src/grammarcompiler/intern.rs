@@ -1,31 +1,45 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
+/// Collects names and assigns them IDs in sorted order, so the IDs do not
+/// depend on the order in which the names are encountered.
 pub struct Interner<'a> {
-    name_to_id: HashMap<&'a str, u32>,
-    names: Vec<(u32, &'a str)>,
+    names: BTreeSet<&'a str>,
 }
 
 impl<'a> Interner<'a> {
     pub fn new() -> Self {
         Interner {
-            name_to_id: HashMap::new(),
-            names: Vec::new(),
+            names: BTreeSet::new(),
         }
     }
 
-    pub fn intern(&mut self, s: &'a str) -> u32 {
-        if let Some(&id) = self.name_to_id.get(s) {
-            id
-        } else {
-            let id = (self.names.len() + 1) as u32;
+    pub fn intern(&mut self, s: &'a str) {
+        self.names.insert(s);
+    }
+
+    pub fn done(self) -> SymbolTable<'a> {
+        let names = (1u32..).zip(self.names).collect::<Vec<_>>();
+        let name_to_id = names.iter().map(|&(id, s)| (s, id)).collect();
 
-            self.names.push((id, s));
-            self.name_to_id.insert(s, id);
-            id
+        SymbolTable {
+            name_to_id: name_to_id,
+            names: names,
         }
     }
+}
+
+pub struct SymbolTable<'a> {
+    name_to_id: HashMap<&'a str, u32>,
+    names: Vec<(u32, &'a str)>,
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Panics if the name was not interned.
+    pub fn id(&self, s: &str) -> u32 {
+        self.name_to_id[s]
+    }
 
-    pub fn done(self) -> Vec<(u32, &'a str)> {
-        self.names
+    pub fn entries(&self) -> &[(u32, &'a str)] {
+        &self.names
     }
 }
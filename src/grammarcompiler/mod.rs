@@ -1,18 +1,24 @@
 use self::errors::*;
-use self::intern::Interner;
-pub use self::ruletoken::RuleId;
+use self::inline::{inline_rules, InlineOptions};
+use self::intern::{Interner, SymbolTable};
+pub use self::ruletoken::{ListId, NestedPosition, NestedType, RuleId, RuleToken, WordId};
 use self::ruletoken::{
-    RuleToken, ALTERNATIVE_END, ALTERNATIVE_START, OPTIONAL_END, OPTIONAL_START, REPETITION_END,
+    ALTERNATIVE_END, ALTERNATIVE_START, OPTIONAL_END, OPTIONAL_START, REPETITION_END,
     REPETITION_START, SEQUENCE_END, SEQUENCE_START,
 };
-use crate::grammar::{Element, Grammar, Rule};
+use crate::backend::GrammarBackend;
+use crate::grammar::{Element, Grammar, Rule, MAX_PERMUTATION_CHILDREN};
 use byteorder::{LittleEndian, WriteBytesExt};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::mem;
 
+pub mod decompiler;
+pub mod inline;
 mod intern;
 mod ruletoken;
+pub mod verifier;
+pub mod wordlists;
 
 pub mod errors {
     use failure::Fail;
@@ -27,18 +33,136 @@ pub mod errors {
         DuplicateRule { name: String },
         #[fail(display = "reserved rule name in grammar definition: {}", name)]
         ReservedRule { name: String },
+        #[fail(display = "import of rule defined in grammar definition: {}", name)]
+        ImportShadowsRule { name: String },
+        #[fail(
+            display = "permutation with {} children in grammar definition (at most {} allowed)",
+            count, max
+        )]
+        PermutationTooLarge { count: usize, max: usize },
+        #[fail(display = "permutation without children in grammar definition")]
+        EmptyPermutation,
+    }
+
+    #[derive(Fail, Debug)]
+    pub enum DecompileError {
+        #[fail(display = "compiled grammar is truncated at offset {}", offset)]
+        Truncated { offset: usize },
+        #[fail(
+            display = "compiled grammar has type {}, expected a command grammar",
+            _0
+        )]
+        NotCommandGrammar(u32),
+        #[fail(
+            display = "unknown rule token {:#x} {:#x} in rule {}",
+            kind, value, rule
+        )]
+        UnknownToken { rule: u32, kind: u16, value: u32 },
+        #[fail(display = "unbalanced nesting tokens in rule {}", rule)]
+        Unbalanced { rule: u32 },
+        #[fail(display = "undeclared word {} in rule {}", id, rule)]
+        UnknownWord { rule: u32, id: u32 },
+        #[fail(display = "undeclared list {} in rule {}", id, rule)]
+        UnknownList { rule: u32, id: u32 },
+        #[fail(display = "undeclared rule {} in rule {}", id, rule)]
+        UnknownRule { rule: u32, id: u32 },
+    }
+
+    #[derive(Fail, Debug)]
+    pub enum VerifyError {
+        #[fail(display = "{}", _0)]
+        Malformed(#[cause] DecompileError),
+        #[fail(display = "unknown grammar type {}", _0)]
+        UnknownGrammarType(u32),
+        #[fail(
+            display = "unexpected chunk {:#x} in grammar of type {}",
+            chunk, grammar
+        )]
+        UnexpectedChunk { grammar: u32, chunk: u32 },
+        #[fail(display = "chunk {:#x} occurs more than once", _0)]
+        DuplicateChunk(u32),
+        #[fail(display = "missing chunk {:#x}", _0)]
+        MissingChunk(u32),
+        #[fail(display = "entry {} in chunk {:#x} is not padded correctly", id, chunk)]
+        Padding { chunk: u32, id: u32 },
+        #[fail(display = "duplicate entry {} in chunk {:#x}", id, chunk)]
+        DuplicateEntry { chunk: u32, id: u32 },
+        #[fail(display = "exported rule {} is not defined", _0)]
+        UndefinedExport(u32),
+    }
+
+    impl From<DecompileError> for VerifyError {
+        fn from(e: DecompileError) -> VerifyError {
+            VerifyError::Malformed(e)
+        }
     }
 }
 
+// grammar types in the header of a compiled grammar
+const COMMAND_GRAMMAR: u32 = 0;
+const DICTATION_GRAMMAR: u32 = 2;
+const SELECT_GRAMMAR: u32 = 10;
+
+/// Compiles a command grammar into Dragon's binary format.
+///
+/// The output only depends on the grammar, so it can be compared, cached
+/// and stored byte for byte:
+///
+/// - the chunks are always written in the order exports, imports, lists,
+///   words, rules, even if they are empty,
+/// - rules get IDs 1 to n in the order in which they are defined,
+/// - the items of separated lists that are more than a single token are
+///   compiled into generated rules that are not exported, which get IDs
+///   n + 1 to n + k in the order in which the lists are compiled,
+/// - words and lists get IDs starting at 1 in the order of their names
+///   (as compared by `str::cmp`),
+/// - the built-in imported rules get IDs n + k + 1 to n + k + 3 (see
+///   `ImportedRule::offset`), and other imported rules get IDs after
+///   those, in the order of their names,
+/// - the entries of every chunk are sorted by ID,
+/// - all rule token probabilities are 0.
 pub fn compile_command_grammar(grammar: &Grammar) -> Result<Vec<u8>> {
     let compiler = GrammarCompiler::new(grammar);
     compiler.compile_grammar()
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Inline rules before compiling. This does not change the words the
+    /// grammar accepts, but the choices reported by a `Matcher` refer to
+    /// the rules of the grammar it was constructed from.
+    pub inline: Option<InlineOptions>,
+}
+
+pub fn compile_command_grammar_with_options(
+    grammar: &Grammar,
+    options: &CompileOptions,
+) -> Result<Vec<u8>> {
+    match options.inline {
+        Some(ref inline) => compile_command_grammar(&inline_rules(grammar, inline)),
+        None => compile_command_grammar(grammar),
+    }
+}
+
+/// Compiles command grammars into Dragon's binary grammar format.
+#[derive(Debug, Clone, Default)]
+pub struct DragonBackend {
+    pub options: CompileOptions,
+}
+
+impl GrammarBackend for DragonBackend {
+    type Output = Vec<u8>;
+    type Error = GrammarError;
+
+    fn compile(&self, grammar: &Grammar) -> Result<Vec<u8>> {
+        compile_command_grammar_with_options(grammar, &self.options)
+    }
+}
+
 pub fn compile_select_grammar(select_words: &[String], through_words: &[String]) -> Vec<u8> {
     let mut output = Vec::new();
 
-    output.write_u32::<LittleEndian>(10).unwrap();
+    output.write_u32::<LittleEndian>(SELECT_GRAMMAR).unwrap();
     output.write_u32::<LittleEndian>(1).unwrap();
 
     let select_chunk = compile_id_chunk(select_words.iter().map(|s| (0, s as &str)));
@@ -52,7 +176,7 @@ pub fn compile_select_grammar(select_words: &[String], through_words: &[String])
 pub fn compile_dictation_grammar() -> Vec<u8> {
     let mut output = Vec::new();
 
-    output.write_u32::<LittleEndian>(2).unwrap();
+    output.write_u32::<LittleEndian>(DICTATION_GRAMMAR).unwrap();
     output.write_u32::<LittleEndian>(1).unwrap();
 
     output
@@ -66,7 +190,7 @@ pub enum ImportedRule {
 }
 
 impl ImportedRule {
-    fn name(&self) -> &'static str {
+    pub fn name(&self) -> &'static str {
         match *self {
             ImportedRule::Dictation => "dgndictation",
             ImportedRule::DictationWord => "dgnwords",
@@ -81,27 +205,134 @@ impl ImportedRule {
             ImportedRule::SpellingLetter => 3,
         }
     }
+
+    pub fn from_name(name: &str) -> Option<ImportedRule> {
+        match name {
+            "dgndictation" => Some(ImportedRule::Dictation),
+            "dgnwords" => Some(ImportedRule::DictationWord),
+            "dgnletters" => Some(ImportedRule::SpellingLetter),
+            _ => None,
+        }
+    }
 }
 
+// other imported rules get IDs after the ones reserved for the
+// built-in rules
+const BUILTIN_IMPORTED_RULES: u32 = 3;
+
 type IdNamePairs<'a> = Vec<(u32, &'a str)>;
 
 struct GrammarCompiler<'a> {
     imported_rules: IdNamePairs<'a>,
     exported_rules: IdNamePairs<'a>,
     rule_name_to_id: HashMap<&'a str, RuleId>,
+    // names of the rules defined by the grammar, which cannot be imported
+    rule_names: HashSet<&'a str>,
+    words: SymbolTable<'a>,
+    lists: SymbolTable<'a>,
+    // imported rules other than the built-in ones
+    imports: SymbolTable<'a>,
+    // the generated rules for the items of separated lists, keyed by
+    // address since permutations compile their children more than once
+    item_rule_ids: HashMap<*const Element, RuleId>,
+    item_rules: Vec<(RuleId, Vec<u8>)>,
+    item_rule_count: u32,
+    grammar: &'a Grammar,
+}
+
+struct Symbols<'a> {
     words: Interner<'a>,
     lists: Interner<'a>,
-    grammar: &'a Grammar,
+    imports: Interner<'a>,
+    item_rules: u32,
+}
+
+// whether an element compiles to a single rule token
+fn is_single_token(element: &Element) -> bool {
+    match *element {
+        Element::Capture { ref child, .. } => is_single_token(child),
+        Element::Word { .. }
+        | Element::RuleRef { .. }
+        | Element::List { .. }
+        | Element::Import { .. }
+        | Element::Dictation { .. }
+        | Element::DictationWord { .. }
+        | Element::SpellingLetter => true,
+        Element::Sequence { .. }
+        | Element::Alternative { .. }
+        | Element::Permutation { .. }
+        | Element::Repetition { .. }
+        | Element::SeparatedList { .. }
+        | Element::Optional { .. } => false,
+    }
+}
+
+fn collect_symbols<'a>(element: &'a Element, symbols: &mut Symbols<'a>) {
+    match *element {
+        Element::Sequence { ref children }
+        | Element::Alternative { ref children }
+        | Element::Permutation { ref children, .. } => {
+            for c in children.iter() {
+                collect_symbols(c, symbols);
+            }
+        }
+        Element::Repetition { ref child }
+        | Element::Optional { ref child }
+        | Element::Capture { ref child, .. } => collect_symbols(child, symbols),
+        Element::SeparatedList {
+            ref child,
+            ref separator,
+            ref last_separator,
+        } => {
+            if !is_single_token(child) {
+                symbols.item_rules += 1;
+            }
+
+            collect_symbols(child, symbols);
+            collect_symbols(separator, symbols);
+            if let Some(ref last) = *last_separator {
+                collect_symbols(last, symbols);
+            }
+        }
+        Element::Word { ref text } => symbols.words.intern(text),
+        Element::List { ref name } => symbols.lists.intern(name),
+        Element::Import { ref name } => {
+            if ImportedRule::from_name(name).is_none() {
+                symbols.imports.intern(name);
+            }
+        }
+        Element::RuleRef { .. }
+        | Element::Dictation { .. }
+        | Element::DictationWord { .. }
+        | Element::SpellingLetter => {}
+    }
 }
 
 impl<'a> GrammarCompiler<'a> {
     fn new(grammar: &'a Grammar) -> Self {
+        // IDs are assigned up front, so they do not depend on the order
+        // in which words and lists are used
+        let mut symbols = Symbols {
+            words: Interner::new(),
+            lists: Interner::new(),
+            imports: Interner::new(),
+            item_rules: 0,
+        };
+        for r in grammar.rules.iter() {
+            collect_symbols(&r.definition, &mut symbols);
+        }
+
         GrammarCompiler {
             imported_rules: Vec::new(),
             exported_rules: Vec::new(),
             rule_name_to_id: HashMap::new(),
-            words: Interner::new(),
-            lists: Interner::new(),
+            rule_names: grammar.rules.iter().map(|r| &r.name as &str).collect(),
+            words: symbols.words.done(),
+            lists: symbols.lists.done(),
+            imports: symbols.imports.done(),
+            item_rule_ids: HashMap::new(),
+            item_rules: Vec::new(),
+            item_rule_count: symbols.item_rules,
             grammar: grammar,
         }
     }
@@ -112,18 +343,24 @@ impl<'a> GrammarCompiler<'a> {
             let compiled = self.compile_rule(id, r)?;
             write_entry(&mut rule_chunk, id, compiled);
         }
+
+        // nested lists finish compiling before the lists containing them
+        self.item_rules.sort_by_key(|&(id, _)| id);
+        for (id, compiled) in mem::replace(&mut self.item_rules, Vec::new()) {
+            write_entry(&mut rule_chunk, id, compiled);
+        }
         let rule_chunk = rule_chunk;
 
-        let words = self.words.done();
-        let word_chunk = compile_id_chunk(words);
-        let lists = self.lists.done();
-        let list_chunk = compile_id_chunk(lists);
+        let word_chunk = compile_id_chunk(self.words.entries().iter().cloned());
+        let list_chunk = compile_id_chunk(self.lists.entries().iter().cloned());
+
+        self.imported_rules.sort_by_key(|&(id, _)| id);
 
         let export_chunk = compile_id_chunk(self.exported_rules);
         let import_chunk = compile_id_chunk(self.imported_rules);
 
         let mut output = Vec::new();
-        output.write_u32::<LittleEndian>(0).unwrap();
+        output.write_u32::<LittleEndian>(COMMAND_GRAMMAR).unwrap();
         output.write_u32::<LittleEndian>(1).unwrap();
         write_chunk(&mut output, ChunkType::Exports, export_chunk);
         write_chunk(&mut output, ChunkType::Imports, import_chunk);
@@ -159,15 +396,33 @@ impl<'a> GrammarCompiler<'a> {
         }
     }
 
-    fn add_imported_rule(&mut self, rule: ImportedRule) -> RuleId {
-        match self.rule_name_to_id.entry(rule.name()) {
-            Entry::Occupied(entry) => *entry.get(),
+    fn add_imported_rule(&mut self, name: &'a str) -> Result<RuleId> {
+        if self.rule_names.contains(name) {
+            // the names of the built-in rules are reserved
+            return Err(if ImportedRule::from_name(name).is_some() {
+                GrammarError::ReservedRule {
+                    name: name.to_string(),
+                }
+            } else {
+                GrammarError::ImportShadowsRule {
+                    name: name.to_string(),
+                }
+            });
+        }
+
+        match self.rule_name_to_id.entry(name) {
+            Entry::Occupied(entry) => Ok(*entry.get()),
             Entry::Vacant(entry) => {
-                let id = self.grammar.rules.len() as u32 + rule.offset();
+                let base = self.grammar.rules.len() as u32 + self.item_rule_count;
+                let id = if let Some(rule) = ImportedRule::from_name(name) {
+                    base + rule.offset()
+                } else {
+                    base + BUILTIN_IMPORTED_RULES + self.imports.id(name)
+                };
 
                 entry.insert(id);
-                self.imported_rules.push((id, rule.name()));
-                id
+                self.imported_rules.push((id, name));
+                Ok(id)
             }
         }
     }
@@ -188,18 +443,74 @@ impl<'a> GrammarCompiler<'a> {
                 }
                 output.push(ALTERNATIVE_END);
             }
+            Element::Permutation {
+                ref children,
+                all_required,
+            } => {
+                if children.is_empty() {
+                    return Err(GrammarError::EmptyPermutation);
+                }
+
+                if children.len() > MAX_PERMUTATION_CHILDREN {
+                    return Err(GrammarError::PermutationTooLarge {
+                        count: children.len(),
+                        max: MAX_PERMUTATION_CHILDREN,
+                    });
+                }
+
+                let remaining = (0..children.len()).collect::<Vec<_>>();
+                self.compile_permutation(children, &remaining, all_required, output)?;
+            }
             Element::Repetition { ref child } => {
                 output.push(REPETITION_START);
                 self.compile_element(child, output)?;
                 output.push(REPETITION_END);
             }
+            Element::SeparatedList {
+                ref child,
+                ref separator,
+                ref last_separator,
+            } => {
+                // the item appears up to three times, so unless it is a
+                // single token it is compiled only once, into a rule of
+                // its own, to keep nested lists from growing exponentially
+                let mut item = Vec::new();
+                if is_single_token(child) {
+                    self.compile_element(child, &mut item)?;
+                } else {
+                    item.push(RuleToken::Rule(self.item_rule(child)?));
+                }
+
+                output.push(SEQUENCE_START);
+                output.extend_from_slice(&item);
+
+                output.push(OPTIONAL_START);
+                output.push(REPETITION_START);
+                output.push(SEQUENCE_START);
+                self.compile_element(separator, output)?;
+                output.extend_from_slice(&item);
+                output.push(SEQUENCE_END);
+                output.push(REPETITION_END);
+                output.push(OPTIONAL_END);
+
+                if let Some(ref last) = *last_separator {
+                    output.push(OPTIONAL_START);
+                    output.push(SEQUENCE_START);
+                    self.compile_element(last, output)?;
+                    output.extend_from_slice(&item);
+                    output.push(SEQUENCE_END);
+                    output.push(OPTIONAL_END);
+                }
+
+                output.push(SEQUENCE_END);
+            }
             Element::Optional { ref child } => {
                 output.push(OPTIONAL_START);
                 self.compile_element(child, output)?;
                 output.push(OPTIONAL_END);
             }
             Element::Word { ref text } => {
-                let id = self.words.intern(text);
+                let id = self.words.id(text);
                 output.push(RuleToken::Word(id));
             }
             Element::RuleRef { ref name } => {
@@ -209,28 +520,82 @@ impl<'a> GrammarCompiler<'a> {
                 output.push(RuleToken::Rule(*result));
             }
             Element::List { ref name } => {
-                let id = self.lists.intern(name);
+                let id = self.lists.id(name);
                 output.push(RuleToken::List(id));
             }
             Element::Capture { ref child, .. } => {
                 self.compile_element(child, output)?;
             }
-            Element::Dictation => {
-                let id = self.add_imported_rule(ImportedRule::Dictation);
+            Element::Import { ref name } => {
+                let id = self.add_imported_rule(name)?;
                 output.push(RuleToken::Rule(id));
             }
-            Element::DictationWord => {
-                let id = self.add_imported_rule(ImportedRule::DictationWord);
+            Element::Dictation { .. } => {
+                let id = self.add_imported_rule(ImportedRule::Dictation.name())?;
+                output.push(RuleToken::Rule(id));
+            }
+            Element::DictationWord { .. } => {
+                let id = self.add_imported_rule(ImportedRule::DictationWord.name())?;
                 output.push(RuleToken::Rule(id));
             }
             Element::SpellingLetter => {
-                let id = self.add_imported_rule(ImportedRule::SpellingLetter);
+                let id = self.add_imported_rule(ImportedRule::SpellingLetter.name())?;
                 output.push(RuleToken::Rule(id));
             }
         };
 
         Ok(())
     }
+
+    fn item_rule(&mut self, child: &'a Element) -> Result<RuleId> {
+        let key = child as *const Element;
+        if let Some(&id) = self.item_rule_ids.get(&key) {
+            return Ok(id);
+        }
+
+        let id = self.grammar.rules.len() as u32 + self.item_rule_ids.len() as u32 + 1;
+        self.item_rule_ids.insert(key, id);
+
+        let mut tokens = Vec::new();
+        self.compile_element(child, &mut tokens)?;
+        self.item_rules.push((id, serialize_rule_tokens(&tokens)));
+
+        Ok(id)
+    }
+
+    fn compile_permutation(
+        &mut self,
+        children: &'a [Element],
+        remaining: &[usize],
+        all_required: bool,
+        output: &mut Vec<RuleToken>,
+    ) -> Result<()> {
+        output.push(ALTERNATIVE_START);
+        for (i, &first) in remaining.iter().enumerate() {
+            let mut rest = remaining.to_vec();
+            rest.remove(i);
+
+            output.push(SEQUENCE_START);
+            self.compile_element(&children[first], output)?;
+
+            if !rest.is_empty() {
+                if !all_required {
+                    output.push(OPTIONAL_START);
+                }
+
+                self.compile_permutation(children, &rest, all_required, output)?;
+
+                if !all_required {
+                    output.push(OPTIONAL_END);
+                }
+            }
+
+            output.push(SEQUENCE_END);
+        }
+        output.push(ALTERNATIVE_END);
+
+        Ok(())
+    }
 }
 
 fn serialize_rule_tokens(tokens: &[RuleToken]) -> Vec<u8> {
@@ -248,8 +613,8 @@ fn serialize_rule_tokens(tokens: &[RuleToken]) -> Vec<u8> {
     result
 }
 
-#[derive(Debug, Copy, Clone)]
-enum ChunkType {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkType {
     Exports = 4,
     Imports = 5,
     Lists = 6,
@@ -259,6 +624,21 @@ enum ChunkType {
     ThroughWords = 0x1018,
 }
 
+impl ChunkType {
+    pub fn from_u32(value: u32) -> Option<ChunkType> {
+        match value {
+            4 => Some(ChunkType::Exports),
+            5 => Some(ChunkType::Imports),
+            6 => Some(ChunkType::Lists),
+            2 => Some(ChunkType::Words),
+            3 => Some(ChunkType::Rules),
+            0x1017 => Some(ChunkType::SelectWords),
+            0x1018 => Some(ChunkType::ThroughWords),
+            _ => None,
+        }
+    }
+}
+
 fn write_chunk(output: &mut Vec<u8>, chunk_type: ChunkType, mut data: Vec<u8>) {
     output.write_u32::<LittleEndian>(chunk_type as u32).unwrap();
     output.write_u32::<LittleEndian>(data.len() as u32).unwrap();
@@ -311,3 +691,86 @@ where
 
     chunk
 }
+
+#[cfg(test)]
+mod tests {
+    use super::decompiler::{decompile_grammar, parse_binary};
+    use super::{compile_command_grammar, ChunkType};
+    use crate::grammar::{Element, Grammar, Rule};
+
+    fn word(text: &str) -> Element {
+        Element::Word {
+            text: text.to_owned(),
+        }
+    }
+
+    fn list(name: &str) -> Element {
+        Element::List {
+            name: name.to_owned(),
+        }
+    }
+
+    fn rule(name: &str, children: Vec<Element>) -> Rule {
+        Rule {
+            name: name.to_owned(),
+            exported: true,
+            definition: Element::Sequence { children: children },
+        }
+    }
+
+    fn chunk(compiled: &[u8], chunk_type: ChunkType) -> Vec<u8> {
+        let binary = parse_binary(compiled).unwrap();
+        binary.chunk(chunk_type).unwrap().data.to_vec()
+    }
+
+    fn grammars() -> (Grammar, Grammar) {
+        let first = rule("first", vec![word("b"), list("dir")]);
+        let second = rule("second", vec![word("a"), list("dir"), word("b")]);
+
+        let forward = Grammar {
+            rules: vec![first.clone(), second.clone()],
+        };
+        let backward = Grammar {
+            rules: vec![second, first],
+        };
+
+        (forward, backward)
+    }
+
+    #[test]
+    fn tables_do_not_depend_on_rule_order() {
+        let (forward, backward) = grammars();
+        let forward = compile_command_grammar(&forward).unwrap();
+        let backward = compile_command_grammar(&backward).unwrap();
+
+        for &t in [ChunkType::Words, ChunkType::Lists].iter() {
+            assert_eq!(chunk(&forward, t), chunk(&backward, t));
+        }
+    }
+
+    #[test]
+    fn golden_tables() {
+        let (grammar, _) = grammars();
+        let compiled = compile_command_grammar(&grammar).unwrap();
+
+        // length, ID and the null-terminated UTF-16 name padded to a
+        // multiple of four bytes, sorted by name
+        let words: &[u8] = &[
+            12, 0, 0, 0, 1, 0, 0, 0, b'a', 0, 0, 0, //
+            12, 0, 0, 0, 2, 0, 0, 0, b'b', 0, 0, 0,
+        ];
+        let lists: &[u8] = &[16, 0, 0, 0, 1, 0, 0, 0, b'd', 0, b'i', 0, b'r', 0, 0, 0];
+
+        assert_eq!(chunk(&compiled, ChunkType::Words), words);
+        assert_eq!(chunk(&compiled, ChunkType::Lists), lists);
+    }
+
+    #[test]
+    fn decompiled_grammar_compiles_to_the_same_binary() {
+        let (grammar, _) = grammars();
+        let compiled = compile_command_grammar(&grammar).unwrap();
+        let recompiled = compile_command_grammar(&decompile_grammar(&compiled).unwrap()).unwrap();
+
+        assert_eq!(recompiled, compiled);
+    }
+}
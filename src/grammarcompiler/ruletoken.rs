@@ -2,13 +2,13 @@ pub type WordId = u32;
 pub type RuleId = u32;
 pub type ListId = u32;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum NestedPosition {
     Start = 1,
     End = 2,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum NestedType {
     Sequence = 1,
     Alternative = 2,
@@ -23,7 +23,7 @@ pub enum BasicType {
     List = 6,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum RuleToken {
     Nested(NestedPosition, NestedType),
     Word(WordId),
@@ -40,6 +40,25 @@ impl RuleToken {
             RuleToken::List(list_id) => (BasicType::List as u16, list_id),
         }
     }
+
+    pub fn from_raw(a: u16, b: u32) -> Option<RuleToken> {
+        let nested_type = || match b {
+            1 => Some(NestedType::Sequence),
+            2 => Some(NestedType::Alternative),
+            3 => Some(NestedType::Repetition),
+            4 => Some(NestedType::Optional),
+            _ => None,
+        };
+
+        match a {
+            1 => nested_type().map(|t| RuleToken::Nested(NestedPosition::Start, t)),
+            2 => nested_type().map(|t| RuleToken::Nested(NestedPosition::End, t)),
+            3 => Some(RuleToken::Word(b)),
+            4 => Some(RuleToken::Rule(b)),
+            6 => Some(RuleToken::List(b)),
+            _ => None,
+        }
+    }
 }
 
 pub const SEQUENCE_START: RuleToken =
@@ -0,0 +1,175 @@
+//! Checks that a compiled grammar is well-formed before it is handed to
+//! Dragon, which tends to respond to malformed grammars with unhelpful
+//! error codes or by crashing.
+
+use super::decompiler::{parse_binary, parse_entries, parse_rule_tokens, Chunk};
+use super::errors::{DecompileError, VerifyError};
+use super::ruletoken::{NestedPosition, NestedType, RuleToken};
+use super::{ChunkType, COMMAND_GRAMMAR, DICTATION_GRAMMAR, SELECT_GRAMMAR};
+use std::collections::HashSet;
+
+pub type Result<T> = ::std::result::Result<T, VerifyError>;
+
+pub fn verify_grammar(data: &[u8]) -> Result<()> {
+    let binary = parse_binary(data)?;
+
+    let (allowed, required): (&[ChunkType], &[ChunkType]) = match binary.grammar_type {
+        COMMAND_GRAMMAR => (
+            &[
+                ChunkType::Exports,
+                ChunkType::Imports,
+                ChunkType::Lists,
+                ChunkType::Words,
+                ChunkType::Rules,
+            ],
+            &[ChunkType::Rules],
+        ),
+        SELECT_GRAMMAR => (
+            &[ChunkType::SelectWords, ChunkType::ThroughWords],
+            &[ChunkType::SelectWords],
+        ),
+        DICTATION_GRAMMAR => (&[], &[]),
+        other => return Err(VerifyError::UnknownGrammarType(other)),
+    };
+
+    let mut seen = HashSet::new();
+    for chunk in binary.chunks.iter() {
+        let known = chunk.known_type();
+        if !known.map_or(false, |t| allowed.contains(&t)) {
+            return Err(VerifyError::UnexpectedChunk {
+                grammar: binary.grammar_type,
+                chunk: chunk.chunk_type,
+            });
+        }
+
+        if !seen.insert(chunk.chunk_type) {
+            return Err(VerifyError::DuplicateChunk(chunk.chunk_type));
+        }
+    }
+
+    for &r in required.iter() {
+        if binary.chunk(r).is_none() {
+            return Err(VerifyError::MissingChunk(r as u32));
+        }
+    }
+
+    if binary.grammar_type == SELECT_GRAMMAR {
+        for chunk in binary.chunks.iter() {
+            // all select words have ID 0, so duplicates are fine
+            verify_id_chunk(chunk, false)?;
+        }
+        return Ok(());
+    }
+
+    if binary.grammar_type != COMMAND_GRAMMAR {
+        return Ok(());
+    }
+
+    let declared = |chunk_type| -> Result<HashSet<u32>> {
+        match binary.chunk(chunk_type) {
+            Some(chunk) => verify_id_chunk(chunk, true),
+            None => Ok(HashSet::new()),
+        }
+    };
+
+    let exports = declared(ChunkType::Exports)?;
+    let imports = declared(ChunkType::Imports)?;
+    let lists = declared(ChunkType::Lists)?;
+    let words = declared(ChunkType::Words)?;
+
+    let rule_chunk = binary
+        .chunk(ChunkType::Rules)
+        .ok_or(VerifyError::MissingChunk(ChunkType::Rules as u32))?;
+    let entries = parse_entries(rule_chunk)?;
+
+    let mut rules = HashSet::new();
+    for e in entries.iter() {
+        if !rules.insert(e.id) || imports.contains(&e.id) {
+            return Err(VerifyError::DuplicateEntry {
+                chunk: rule_chunk.chunk_type,
+                id: e.id,
+            });
+        }
+    }
+
+    for &id in exports.iter() {
+        if !rules.contains(&id) {
+            return Err(VerifyError::UndefinedExport(id));
+        }
+    }
+
+    for e in entries.iter() {
+        let tokens = parse_rule_tokens(e.id, e.data)?;
+        verify_rule(e.id, &tokens, &words, &lists, &rules, &imports)?;
+    }
+
+    Ok(())
+}
+
+fn verify_id_chunk(chunk: &Chunk, unique: bool) -> Result<HashSet<u32>> {
+    let mut ids = HashSet::new();
+
+    for e in parse_entries(chunk)?.iter() {
+        // names are padded to a multiple of four bytes, and terminated
+        // by at least two null bytes
+        let padded = e.data.len() % 4 == 0 && e.data.len() >= 4 && e.data.ends_with(&[0, 0]);
+        if !padded {
+            return Err(VerifyError::Padding {
+                chunk: chunk.chunk_type,
+                id: e.id,
+            });
+        }
+
+        if !ids.insert(e.id) && unique {
+            return Err(VerifyError::DuplicateEntry {
+                chunk: chunk.chunk_type,
+                id: e.id,
+            });
+        }
+    }
+
+    Ok(ids)
+}
+
+fn verify_rule(
+    rule: u32,
+    tokens: &[RuleToken],
+    words: &HashSet<u32>,
+    lists: &HashSet<u32>,
+    rules: &HashSet<u32>,
+    imports: &HashSet<u32>,
+) -> Result<()> {
+    let mut stack: Vec<NestedType> = Vec::new();
+
+    for t in tokens.iter() {
+        match *t {
+            RuleToken::Nested(NestedPosition::Start, ty) => stack.push(ty),
+            RuleToken::Nested(NestedPosition::End, ty) => {
+                if stack.pop() != Some(ty) {
+                    return Err(DecompileError::Unbalanced { rule: rule }.into());
+                }
+            }
+            RuleToken::Word(id) => {
+                if !words.contains(&id) {
+                    return Err(DecompileError::UnknownWord { rule: rule, id: id }.into());
+                }
+            }
+            RuleToken::List(id) => {
+                if !lists.contains(&id) {
+                    return Err(DecompileError::UnknownList { rule: rule, id: id }.into());
+                }
+            }
+            RuleToken::Rule(id) => {
+                if !rules.contains(&id) && !imports.contains(&id) {
+                    return Err(DecompileError::UnknownRule { rule: rule, id: id }.into());
+                }
+            }
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err(DecompileError::Unbalanced { rule: rule }.into());
+    }
+
+    Ok(())
+}
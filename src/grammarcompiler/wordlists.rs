@@ -0,0 +1,210 @@
+//! Conversion of large word alternatives into lists.
+//!
+//! Dragon is slow to load and update grammars that contain alternatives
+//! with hundreds of words, but handles lists of the same size well. This
+//! pass replaces alternatives consisting only of words with references
+//! to synthetic lists, which have to be filled after the grammar has
+//! been loaded. Dragon reports the recognized list entry as the word
+//! itself, so results can be matched against the original grammar and
+//! produce the same captures. The `Matcher` should therefore be
+//! constructed from the original grammar rather than the converted one.
+
+use crate::grammar::{Element, Grammar, Rule};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone)]
+pub struct WordListOptions {
+    /// Alternatives with at least this many distinct words are turned
+    /// into lists.
+    pub min_words: usize,
+}
+
+impl Default for WordListOptions {
+    fn default() -> Self {
+        WordListOptions { min_words: 50 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordList {
+    pub name: String,
+    pub words: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WordListGrammar {
+    /// The grammar with word alternatives replaced by list references.
+    pub grammar: Grammar,
+    /// The lists referenced by the converted grammar, along with the
+    /// words they need to be filled with.
+    pub lists: Vec<WordList>,
+}
+
+pub fn extract_word_lists(grammar: &Grammar, options: &WordListOptions) -> WordListGrammar {
+    let mut used_names = HashSet::new();
+    for r in grammar.rules.iter() {
+        collect_list_names(&r.definition, &mut used_names);
+    }
+
+    let mut extractor = Extractor {
+        options: options,
+        used_names: used_names,
+        words_to_list: HashMap::new(),
+        lists: Vec::new(),
+    };
+
+    let rules = grammar
+        .rules
+        .iter()
+        .map(|r| Rule {
+            name: r.name.clone(),
+            exported: r.exported,
+            definition: extractor.convert(&r.definition),
+        })
+        .collect();
+
+    WordListGrammar {
+        grammar: Grammar { rules: rules },
+        lists: extractor.lists,
+    }
+}
+
+fn collect_list_names(element: &Element, names: &mut HashSet<String>) {
+    match *element {
+        Element::Sequence { ref children }
+        | Element::Alternative { ref children }
+        | Element::Permutation { ref children, .. } => {
+            for c in children.iter() {
+                collect_list_names(c, names);
+            }
+        }
+        Element::Repetition { ref child }
+        | Element::Optional { ref child }
+        | Element::Capture { ref child, .. } => collect_list_names(child, names),
+        Element::SeparatedList {
+            ref child,
+            ref separator,
+            ref last_separator,
+        } => {
+            collect_list_names(child, names);
+            collect_list_names(separator, names);
+            if let Some(ref last) = *last_separator {
+                collect_list_names(last, names);
+            }
+        }
+        Element::List { ref name } => {
+            names.insert(name.clone());
+        }
+        Element::Word { .. }
+        | Element::RuleRef { .. }
+        | Element::Import { .. }
+        | Element::Dictation { .. }
+        | Element::DictationWord { .. }
+        | Element::SpellingLetter => {}
+    }
+}
+
+struct Extractor<'a> {
+    options: &'a WordListOptions,
+    used_names: HashSet<String>,
+    // alternatives over the same words share a list
+    words_to_list: HashMap<Vec<String>, String>,
+    lists: Vec<WordList>,
+}
+
+impl<'a> Extractor<'a> {
+    fn convert(&mut self, element: &Element) -> Element {
+        match *element {
+            Element::Sequence { ref children } => Element::Sequence {
+                children: self.convert_all(children),
+            },
+            Element::Alternative { ref children } => {
+                if let Some(name) = self.word_list(children) {
+                    return Element::List { name: name };
+                }
+
+                Element::Alternative {
+                    children: self.convert_all(children),
+                }
+            }
+            Element::Permutation {
+                ref children,
+                all_required,
+            } => Element::Permutation {
+                children: self.convert_all(children),
+                all_required: all_required,
+            },
+            Element::Repetition { ref child } => Element::Repetition {
+                child: Box::new(self.convert(child)),
+            },
+            Element::SeparatedList {
+                ref child,
+                ref separator,
+                ref last_separator,
+            } => Element::SeparatedList {
+                child: Box::new(self.convert(child)),
+                separator: Box::new(self.convert(separator)),
+                last_separator: last_separator.as_ref().map(|l| Box::new(self.convert(l))),
+            },
+            Element::Optional { ref child } => Element::Optional {
+                child: Box::new(self.convert(child)),
+            },
+            Element::Capture {
+                ref name,
+                ref child,
+            } => Element::Capture {
+                name: name.clone(),
+                child: Box::new(self.convert(child)),
+            },
+            _ => element.clone(),
+        }
+    }
+
+    fn convert_all(&mut self, children: &[Element]) -> Vec<Element> {
+        children.iter().map(|c| self.convert(c)).collect()
+    }
+
+    fn word_list(&mut self, children: &[Element]) -> Option<String> {
+        let mut seen = HashSet::new();
+        let mut words = Vec::new();
+
+        for c in children.iter() {
+            match *c {
+                Element::Word { ref text } => {
+                    if seen.insert(text as &str) {
+                        words.push(text.clone());
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        if words.is_empty() || words.len() < self.options.min_words {
+            return None;
+        }
+
+        if let Some(name) = self.words_to_list.get(&words) {
+            return Some(name.clone());
+        }
+
+        let name = self.new_name();
+        self.words_to_list.insert(words.clone(), name.clone());
+        self.lists.push(WordList {
+            name: name.clone(),
+            words: words,
+        });
+
+        Some(name)
+    }
+
+    fn new_name(&mut self) -> String {
+        let mut i = self.lists.len() + 1;
+        loop {
+            let name = format!("_words_{}", i);
+            if self.used_names.insert(name.clone()) {
+                return name;
+            }
+            i += 1;
+        }
+    }
+}
@@ -2,7 +2,7 @@
 #![cfg(target_arch = "x86")]
 #![cfg(target_env = "msvc")]
 pub mod errors {
-    use crate::grammarcompiler::errors::GrammarError;
+    use crate::grammarcompiler::errors::{GrammarError, VerifyError};
     use components::errors::ComError;
     use failure::Fail;
 
@@ -14,6 +14,8 @@ pub mod errors {
         Com(#[cause] ComError),
         #[fail(display = "{}", _0)]
         Grammar(#[cause] GrammarError),
+        #[fail(display = "invalid compiled grammar: {}", _0)]
+        InvalidGrammar(#[cause] VerifyError),
         #[fail(display = "attempt to perform operation on unloaded grammar")]
         GrammarGone,
     }
@@ -29,14 +31,24 @@ pub mod errors {
             Error::Grammar(e)
         }
     }
+
+    impl From<VerifyError> for Error {
+        fn from(e: VerifyError) -> Error {
+            Error::InvalidGrammar(e)
+        }
+    }
 }
 
+pub mod backend;
+pub mod cache;
 pub mod engine;
+pub mod fstcompiler;
 pub mod grammar;
+pub mod grammarcompiler;
+pub mod loader;
 pub mod resultparser;
 
 mod dragon;
-mod grammarcompiler;
 mod interfaces;
 
 use errors::*;
@@ -0,0 +1,389 @@
+//! Loading of grammars from a directory.
+//!
+//! Every supported file in the directory becomes a grammar named after
+//! the file (without its extension). A grammar file can include other
+//! files from the same directory, which adds their rules (and the rules
+//! of the files they include) to the including grammar.
+//!
+//! JSON is the only supported format. The crate has no text syntax for
+//! grammars, only the serde representation of `grammar::Grammar`, so
+//! files with extensions other than `.json` are ignored:
+//!
+//! ```json
+//! {
+//!     "include": ["numbers.json"],
+//!     "rules": [ ... ]
+//! }
+//! ```
+
+use crate::grammar::{Grammar, Rule};
+use failure::Fail;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Fail, Debug)]
+pub enum LoaderError {
+    #[fail(display = "could not read {}: {}", path, cause)]
+    Io {
+        path: String,
+        #[cause]
+        cause: io::Error,
+    },
+    #[fail(display = "could not parse {}: {}", path, cause)]
+    Parse {
+        path: String,
+        #[cause]
+        cause: serde_json::Error,
+    },
+    #[fail(display = "two grammars named {} in directory", name)]
+    DuplicateGrammar { name: String },
+    #[fail(display = "{}: unknown include {}", location, include)]
+    UnknownInclude { location: Location, include: String },
+    #[fail(display = "include cycle: {}", _0)]
+    IncludeCycle(String),
+    #[fail(
+        display = "{}: duplicate rule {} (first defined at {})",
+        second, name, first
+    )]
+    DuplicateRule {
+        name: String,
+        first: Location,
+        second: Location,
+    },
+}
+
+pub type Result<T> = ::std::result::Result<T, LoaderError>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: PathBuf,
+    /// `None` if the line could not be determined.
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.file.display(), line),
+            None => write!(f, "{}", self.file.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedGrammar {
+    pub grammar: Grammar,
+    pub locations: HashMap<String, Location>,
+}
+
+impl LoadedGrammar {
+    pub fn location(&self, rule: &str) -> Option<&Location> {
+        self.locations.get(rule)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GrammarFile {
+    #[serde(default)]
+    include: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+struct SourceFile {
+    path: PathBuf,
+    file_name: String,
+    includes: Vec<(String, Option<usize>)>,
+    rules: Vec<(Rule, Option<usize>)>,
+}
+
+pub fn load_directory<P: AsRef<Path>>(directory: P) -> Result<BTreeMap<String, LoadedGrammar>> {
+    let directory = directory.as_ref();
+    let io_error = |cause| LoaderError::Io {
+        path: directory.display().to_string(),
+        cause: cause,
+    };
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(directory).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path.is_file() && is_supported(&path) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut files = BTreeMap::new();
+    for path in paths {
+        let name = grammar_name(&path);
+        let source = read_source(path)?;
+
+        if files.insert(name.clone(), source).is_some() {
+            return Err(LoaderError::DuplicateGrammar { name: name });
+        }
+    }
+
+    let resolver = Resolver { files: &files };
+    let mut grammars = BTreeMap::new();
+    for name in files.keys() {
+        grammars.insert(name.clone(), resolver.resolve(name)?);
+    }
+
+    Ok(grammars)
+}
+
+/// Loads a single grammar file, along with the files it includes from
+/// the same directory. Other files in the directory are not read.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<LoadedGrammar> {
+    let path = path.as_ref();
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let name = grammar_name(path);
+
+    let mut files = BTreeMap::new();
+    let mut pending = vec![(name.clone(), path.to_path_buf())];
+    while let Some((name, path)) = pending.pop() {
+        if files.contains_key(&name) {
+            continue;
+        }
+
+        let source = read_source(path)?;
+
+        // includes that do not exist are reported by the resolver
+        for &(ref include, _) in source.includes.iter() {
+            let included = if is_supported(Path::new(include)) {
+                directory.join(include)
+            } else {
+                directory.join(format!("{}.json", include))
+            };
+
+            if included.is_file() {
+                pending.push((grammar_name(&included), included));
+            }
+        }
+
+        files.insert(name, source);
+    }
+
+    let resolver = Resolver { files: &files };
+    let name = files.keys().find(|&n| *n == name).unwrap();
+    resolver.resolve(name)
+}
+
+fn grammar_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn is_supported(path: &Path) -> bool {
+    path.extension().map_or(false, |e| e == "json")
+}
+
+fn read_source(path: PathBuf) -> Result<SourceFile> {
+    let text = fs::read_to_string(&path).map_err(|e| LoaderError::Io {
+        path: path.display().to_string(),
+        cause: e,
+    })?;
+
+    let parsed: GrammarFile = serde_json::from_str(&text).map_err(|e| LoaderError::Parse {
+        path: path.display().to_string(),
+        cause: e,
+    })?;
+
+    let include_lines = element_lines(&text, "include", parsed.include.len());
+    let rule_lines = element_lines(&text, "rules", parsed.rules.len());
+
+    let file_name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Ok(SourceFile {
+        path: path,
+        file_name: file_name,
+        includes: parsed.include.into_iter().zip(include_lines).collect(),
+        rules: parsed.rules.into_iter().zip(rule_lines).collect(),
+    })
+}
+
+// Finds the line on which each element of the array stored under the
+// given key of the top-level object starts. The text must be valid
+// JSON. The scanner is not a full parser, so if it does not find the
+// expected number of elements, none of the lines are reported rather
+// than the wrong ones.
+fn element_lines(text: &str, key: &str, expected: usize) -> Vec<Option<usize>> {
+    let lines = scan_element_lines(text, key);
+    if lines.len() == expected {
+        lines.into_iter().map(Some).collect()
+    } else {
+        vec![None; expected]
+    }
+}
+
+fn scan_element_lines(text: &str, key: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut line = 1;
+    let mut depth = 0;
+
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut string_start = 0;
+    let mut last_string = Cow::Borrowed("");
+
+    let mut at_key = false;
+    let mut in_target = false;
+    let mut expect_element = false;
+
+    for (i, c) in text.char_indices() {
+        if c == '\n' {
+            line += 1;
+        }
+
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+                last_string = unescape(&text[string_start - 1..=i]);
+            }
+            continue;
+        }
+
+        if in_target && depth == 2 && expect_element && !c.is_whitespace() && c != ']' {
+            lines.push(line);
+            expect_element = false;
+        }
+
+        match c {
+            '"' => {
+                in_string = true;
+                string_start = i + 1;
+            }
+            ':' if depth == 1 => at_key = last_string == key,
+            ',' if depth == 1 => at_key = false,
+            ',' if in_target && depth == 2 => expect_element = true,
+            '{' | '[' => {
+                depth += 1;
+                if depth == 2 && c == '[' && at_key {
+                    in_target = true;
+                    expect_element = true;
+                }
+            }
+            '}' | ']' => {
+                if depth == 2 {
+                    in_target = false;
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+
+    lines
+}
+
+// takes a string literal including its quotes
+fn unescape(literal: &str) -> Cow<str> {
+    let contents = &literal[1..literal.len() - 1];
+    if contents.contains('\\') {
+        Cow::Owned(serde_json::from_str(literal).unwrap_or_default())
+    } else {
+        Cow::Borrowed(contents)
+    }
+}
+
+struct Resolver<'a> {
+    files: &'a BTreeMap<String, SourceFile>,
+}
+
+impl<'a> Resolver<'a> {
+    fn lookup(&self, include: &str) -> Option<&'a str> {
+        self.files
+            .iter()
+            .find(|&(name, f)| f.file_name == include || name == include)
+            .map(|(name, _)| name as &str)
+    }
+
+    fn resolve(&self, name: &'a str) -> Result<LoadedGrammar> {
+        let mut result = LoadedGrammar {
+            grammar: Grammar { rules: Vec::new() },
+            locations: HashMap::new(),
+        };
+
+        let mut active = Vec::new();
+        let mut done = HashSet::new();
+        self.add_file(name, &mut active, &mut done, &mut result)?;
+
+        Ok(result)
+    }
+
+    fn add_file(
+        &self,
+        name: &'a str,
+        active: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+        result: &mut LoadedGrammar,
+    ) -> Result<()> {
+        if active.contains(&name) {
+            let mut cycle = active
+                .iter()
+                .skip_while(|&&n| n != name)
+                .map(|n| self.files[*n].file_name.clone())
+                .collect::<Vec<_>>();
+            cycle.push(self.files[name].file_name.clone());
+
+            return Err(LoaderError::IncludeCycle(cycle.join(" -> ")));
+        }
+
+        // a file which is included several times only contributes its
+        // rules once
+        if !done.insert(name) {
+            return Ok(());
+        }
+
+        let file = &self.files[name];
+        active.push(name);
+
+        for &(ref include, line) in file.includes.iter() {
+            let included = self
+                .lookup(include)
+                .ok_or_else(|| LoaderError::UnknownInclude {
+                    location: Location {
+                        file: file.path.clone(),
+                        line: line,
+                    },
+                    include: include.clone(),
+                })?;
+
+            self.add_file(included, active, done, result)?;
+        }
+
+        active.pop();
+
+        for &(ref rule, line) in file.rules.iter() {
+            let location = Location {
+                file: file.path.clone(),
+                line: line,
+            };
+
+            if let Some(first) = result.locations.get(&rule.name) {
+                return Err(LoaderError::DuplicateRule {
+                    name: rule.name.clone(),
+                    first: first.clone(),
+                    second: location,
+                });
+            }
+
+            result.locations.insert(rule.name.clone(), location);
+            result.grammar.rules.push(rule.clone());
+        }
+
+        Ok(())
+    }
+}
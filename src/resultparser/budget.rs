@@ -0,0 +1,70 @@
+use super::MatchError;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// reading the clock on every step would dominate the running time
+const STEPS_PER_CLOCK_CHECK: u64 = 1024;
+
+/// Limits on the work done for a single match, so a pathological
+/// utterance fails with `MatchError::BudgetExceeded` instead of stalling
+/// the thread it is matched on.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchBudget {
+    /// The maximum number of instructions executed.
+    pub max_steps: Option<u64>,
+    /// The maximum time spent matching.
+    pub max_duration: Option<Duration>,
+}
+
+/// At most a million steps, which is far more than any realistic
+/// utterance needs, and no time limit.
+impl Default for MatchBudget {
+    fn default() -> Self {
+        MatchBudget {
+            max_steps: Some(1_000_000),
+            max_duration: None,
+        }
+    }
+}
+
+impl MatchBudget {
+    pub fn unlimited() -> Self {
+        MatchBudget {
+            max_steps: None,
+            max_duration: None,
+        }
+    }
+}
+
+pub struct Budget {
+    max_steps: Option<u64>,
+    deadline: Option<Instant>,
+    steps: u64,
+}
+
+impl Budget {
+    pub fn new(budget: &MatchBudget) -> Self {
+        Budget {
+            max_steps: budget.max_steps,
+            // a duration too large to represent is no limit at all
+            deadline: budget
+                .max_duration
+                .and_then(|d| Instant::now().checked_add(d)),
+            steps: 0,
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), MatchError> {
+        self.steps += 1;
+
+        let out_of_steps = self.max_steps.map_or(false, |max| self.steps > max);
+        let out_of_time = self.steps % STEPS_PER_CLOCK_CHECK == 0
+            && self.deadline.map_or(false, |d| Instant::now() >= d);
+
+        if out_of_steps || out_of_time {
+            return Err(MatchError::BudgetExceeded { steps: self.steps });
+        }
+
+        Ok(())
+    }
+}
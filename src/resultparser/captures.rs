@@ -1,10 +1,32 @@
+use super::MatchError;
 use serde::Serialize;
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub struct CaptureTree<'a, T> {
     pub name: &'a str,
     pub slice: T,
     pub children: Vec<CaptureTree<'a, T>>,
+    /// The list entries matched inside this capture, but not inside any
+    /// of its children.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub list_entries: Vec<ListMatch<'a>>,
+    /// The spelling letters matched inside this capture, but not inside
+    /// any of its children.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub letters: Vec<SpelledLetter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct ListMatch<'a> {
+    pub list: &'a str,
+    pub entry: String,
+    pub slice: (usize, usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct SpelledLetter {
+    pub position: usize,
+    pub letter: char,
 }
 
 pub type Match<'a> = CaptureTree<'a, (usize, usize)>;
@@ -16,23 +38,27 @@ enum Capture {
 }
 
 impl Capture {
-    fn complete(&self) -> (usize, usize) {
+    fn complete(&self) -> Result<(usize, usize), MatchError> {
         if let Capture::Stopped(a, b) = *self {
-            (a, b)
+            Ok((a, b))
         } else {
-            panic!("attempt to unwrap incomplete capture");
+            Err(MatchError::InvalidProgram(
+                "capture is never stopped".to_owned(),
+            ))
         }
     }
 }
 
-fn complete_capture_tree<'a>(tree: &CaptureTree<'a, Capture>) -> Match<'a> {
+fn complete_capture_tree<'a>(tree: &CaptureTree<'a, Capture>) -> Result<Match<'a>, MatchError> {
     let completed_children = tree.children.iter().map(|c| complete_capture_tree(c));
 
-    CaptureTree {
+    Ok(CaptureTree {
         name: tree.name,
-        slice: tree.slice.complete(),
-        children: completed_children.collect(),
-    }
+        slice: tree.slice.complete()?,
+        children: completed_children.collect::<Result<_, _>>()?,
+        list_entries: tree.list_entries.clone(),
+        letters: tree.letters.clone(),
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -52,22 +78,56 @@ impl<'a> CaptureBuilder<'a> {
             name: name,
             slice: Capture::Started(position),
             children: Vec::new(),
+            list_entries: Vec::new(),
+            letters: Vec::new(),
         });
     }
 
-    pub fn capture_stop(&mut self, position: usize) {
+    // the innermost capture that has not been stopped yet
+    fn open_capture(&mut self) -> Option<&mut CaptureTree<'a, Capture>> {
+        match self.captures.last_mut() {
+            Some(capture) => match capture.slice {
+                Capture::Started(_) => Some(capture),
+                Capture::Stopped(..) => None,
+            },
+            None => None,
+        }
+    }
+
+    pub fn list_entry(&mut self, list: &'a str, entry: &str, slice: (usize, usize)) {
+        if let Some(capture) = self.open_capture() {
+            capture.list_entries.push(ListMatch {
+                list: list,
+                entry: entry.to_owned(),
+                slice: slice,
+            });
+        }
+    }
+
+    pub fn letter(&mut self, position: usize, letter: char) {
+        if let Some(capture) = self.open_capture() {
+            capture.letters.push(SpelledLetter {
+                position: position,
+                letter: letter,
+            });
+        }
+    }
+
+    pub fn capture_stop(&mut self, position: usize) -> Result<(), MatchError> {
         {
-            let child = self.captures.last_mut().unwrap();
+            let invalid =
+                || MatchError::InvalidProgram("capture stopped without being started".to_owned());
+            let child = self.captures.last_mut().ok_or_else(invalid)?;
 
             if let Capture::Started(start) = child.slice {
                 child.slice = Capture::Stopped(start, position);
             } else {
-                panic!("attempt to stop capture twice");
+                return Err(invalid());
             }
         }
 
         if self.captures.len() < 2 {
-            return;
+            return Ok(());
         }
 
         let child = self.captures.pop().unwrap();
@@ -76,14 +136,15 @@ impl<'a> CaptureBuilder<'a> {
             let parent = self.captures.last_mut().unwrap();
             if let Capture::Started(_) = parent.slice {
                 parent.children.push(child);
-                return;
+                return Ok(());
             }
         }
 
         self.captures.push(child);
+        Ok(())
     }
 
-    pub fn done(self) -> Vec<Match<'a>> {
+    pub fn done(self) -> Result<Vec<Match<'a>>, MatchError> {
         self.captures
             .iter()
             .map(|c| complete_capture_tree(c))
@@ -1,13 +1,48 @@
-use super::instructions::{Instruction, JumpTarget, LabelName};
-use crate::grammar::{Element, Grammar, Rule};
-use std::collections::HashMap;
+use super::instructions::{Choice, Instruction, JumpTarget, LabelName};
+use super::leftrec::find_left_recursion;
+use super::{MatcherError, MatcherOptions};
+use crate::grammar::{Element, Grammar, Rule, MAX_PERMUTATION_CHILDREN};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+
+type Result<T> = ::std::result::Result<T, MatcherError>;
+
+// the words up to the minimum of a dictation are compiled one by one
+const MAX_DICTATION_WORDS: usize = 100;
+
+/// The words matched in place of an imported rule, which the matcher
+/// knows nothing about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Placeholder {
+    /// Exactly one word.
+    Word,
+    /// One or more words, as few as possible.
+    Words,
+}
+
+fn default_placeholder(name: &str) -> Placeholder {
+    match name {
+        "dgnwords" | "dgnletters" => Placeholder::Word,
+        _ => Placeholder::Words,
+    }
+}
+
+pub fn compile_matcher(grammar: &Grammar, options: &MatcherOptions) -> Result<Vec<Instruction>> {
+    let left_recursion = find_left_recursion(grammar);
+    if let Some(ref cycle) = left_recursion.cycle {
+        if !options.left_recursion {
+            return Err(MatcherError::LeftRecursion(cycle.join(" -> ")));
+        }
+    }
 
-pub fn compile_matcher(grammar: &Grammar) -> Vec<Instruction> {
-    let compiler = Compiler::new();
-    let mut instructions = compiler.compile_grammar(grammar);
+    let compiler = Compiler::new(&options.placeholders, left_recursion.rules);
+    let mut instructions = compiler.compile_grammar(grammar)?;
     let locations = find_label_locations(&instructions);
     relabel(&mut instructions, &locations);
-    instructions
+    Ok(instructions)
 }
 
 fn find_label_locations(instructions: &[Instruction]) -> HashMap<LabelName, usize> {
@@ -31,7 +66,9 @@ fn relabel(instructions: &mut [Instruction], locations: &HashMap<LabelName, usiz
 
     for i in instructions.iter_mut() {
         match *i {
-            Instruction::Jump(ref mut target) | Instruction::RuleCall(ref mut target) => {
+            Instruction::Jump(ref mut target)
+            | Instruction::RuleCall(ref mut target)
+            | Instruction::GuardedCall(ref mut target) => {
                 relabel_target(target, locations);
             }
             Instruction::Split(ref mut targets) => {
@@ -47,6 +84,41 @@ fn relabel(instructions: &mut [Instruction], locations: &HashMap<LabelName, usiz
     }
 }
 
+fn collect_keywords<'a>(element: &'a Element, keywords: &mut BTreeSet<&'a str>) {
+    match *element {
+        Element::Sequence { ref children }
+        | Element::Alternative { ref children }
+        | Element::Permutation { ref children, .. } => {
+            for c in children.iter() {
+                collect_keywords(c, keywords);
+            }
+        }
+        Element::Repetition { ref child }
+        | Element::Optional { ref child }
+        | Element::Capture { ref child, .. } => collect_keywords(child, keywords),
+        Element::SeparatedList {
+            ref child,
+            ref separator,
+            ref last_separator,
+        } => {
+            collect_keywords(child, keywords);
+            collect_keywords(separator, keywords);
+            if let Some(ref last) = *last_separator {
+                collect_keywords(last, keywords);
+            }
+        }
+        Element::Word { ref text } => {
+            keywords.insert(text);
+        }
+        Element::RuleRef { .. }
+        | Element::List { .. }
+        | Element::Import { .. }
+        | Element::Dictation { .. }
+        | Element::DictationWord { .. }
+        | Element::SpellingLetter => {}
+    }
+}
+
 fn make_split(labels: &[LabelName]) -> Instruction {
     let split = labels
         .iter()
@@ -57,15 +129,32 @@ fn make_split(labels: &[LabelName]) -> Instruction {
 }
 
 struct Compiler<'a> {
+    placeholders: &'a HashMap<String, Placeholder>,
+    // calls to these rules need to be guarded against infinite recursion
+    left_recursive: HashSet<&'a str>,
     rule_name_to_label: HashMap<&'a str, LabelName>,
+    // the rule being compiled and the number of alternatives compiled
+    // so far, which numbers them in the order they appear in the rule
+    rule: &'a str,
+    alternatives: usize,
+    // the words of the grammar, which dictation words can exclude
+    keywords: Arc<HashSet<String>>,
     label_counter: u32,
     instructions: Vec<Instruction>,
 }
 
 impl<'a> Compiler<'a> {
-    fn new() -> Self {
+    fn new(
+        placeholders: &'a HashMap<String, Placeholder>,
+        left_recursive: HashSet<&'a str>,
+    ) -> Self {
         Compiler {
+            placeholders: placeholders,
+            left_recursive: left_recursive,
             rule_name_to_label: HashMap::new(),
+            rule: "",
+            alternatives: 0,
+            keywords: Arc::new(HashSet::new()),
             label_counter: 0,
             instructions: Vec::new(),
         }
@@ -81,7 +170,13 @@ impl<'a> Compiler<'a> {
         LabelName(self.label_counter)
     }
 
-    fn compile_grammar(mut self, grammar: &'a Grammar) -> Vec<Instruction> {
+    fn compile_grammar(mut self, grammar: &'a Grammar) -> Result<Vec<Instruction>> {
+        let mut keywords = BTreeSet::new();
+        for r in grammar.rules.iter() {
+            collect_keywords(&r.definition, &mut keywords);
+        }
+        self.keywords = Arc::new(keywords.into_iter().map(|k| k.to_owned()).collect());
+
         let mut with_labels = Vec::new();
         let mut split_labels = Vec::new();
         for r in grammar.rules.iter() {
@@ -90,32 +185,49 @@ impl<'a> Compiler<'a> {
                 split_labels.push(n);
             }
             with_labels.push((r, n));
-            self.rule_name_to_label.insert(&r.name, n);
+
+            match self.rule_name_to_label.entry(&r.name) {
+                Entry::Occupied(_) => {
+                    return Err(MatcherError::DuplicateRule {
+                        name: r.name.clone(),
+                    });
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(n);
+                }
+            }
         }
 
         self.emit(make_split(&split_labels));
 
         for &(r, label) in &with_labels {
-            self.compile_single_rule(r, label);
+            self.compile_single_rule(r, label)?;
         }
 
-        self.instructions
+        Ok(self.instructions)
     }
 
-    fn compile_single_rule(&mut self, rule: &'a Rule, start_label: LabelName) {
+    fn compile_single_rule(&mut self, rule: &'a Rule, start_label: LabelName) -> Result<()> {
+        self.rule = &rule.name;
+        self.alternatives = 0;
+
         self.emit(Instruction::Label(start_label));
-        self.compile_element(&rule.definition);
+        self.compile_element(&rule.definition)?;
         self.emit(Instruction::Return);
+        Ok(())
     }
 
-    fn compile_element(&mut self, element: &'a Element) {
+    fn compile_element(&mut self, element: &'a Element) -> Result<()> {
         match *element {
             Element::Sequence { ref children } => {
                 for c in children.iter() {
-                    self.compile_element(c);
+                    self.compile_element(c)?;
                 }
             }
             Element::Alternative { ref children } => {
+                let alternative = self.alternatives;
+                self.alternatives += 1;
+
                 let mut labels = Vec::new();
                 for _ in 0..children.len() {
                     labels.push(self.new_label());
@@ -124,16 +236,69 @@ impl<'a> Compiler<'a> {
                 self.emit(make_split(&labels));
 
                 let end = self.new_label();
-                for (start, c) in labels.iter().zip(children.iter()) {
+                for (i, (start, c)) in labels.iter().zip(children.iter()).enumerate() {
                     self.emit(Instruction::Label(*start));
+                    self.emit(Instruction::Choice(Choice {
+                        rule: self.rule.to_owned(),
+                        alternative: alternative,
+                        child: i,
+                    }));
 
-                    self.compile_element(c);
+                    self.compile_element(c)?;
 
                     self.emit(Instruction::Jump(JumpTarget::Symbolic(end)));
                 }
 
                 self.emit(Instruction::Label(end));
             }
+            Element::Permutation {
+                ref children,
+                all_required,
+            } => {
+                // each iteration of the loop claims a child that has
+                // not been matched yet, so there are no more than
+                // children.len() iterations
+                if children.is_empty() {
+                    return Err(MatcherError::EmptyPermutation);
+                }
+
+                if children.len() > MAX_PERMUTATION_CHILDREN {
+                    return Err(MatcherError::PermutationTooLarge {
+                        count: children.len(),
+                        max: MAX_PERMUTATION_CHILDREN,
+                    });
+                }
+
+                let loop_label = self.new_label();
+                let done_label = self.new_label();
+
+                let mut labels = Vec::new();
+                for _ in 0..children.len() {
+                    labels.push(self.new_label());
+                }
+
+                self.emit(Instruction::PermutationStart);
+                self.emit(Instruction::Label(loop_label));
+
+                // greedy since done label is put last
+                labels.push(done_label);
+                self.emit(make_split(&labels));
+
+                for (i, (start, c)) in labels.iter().zip(children.iter()).enumerate() {
+                    self.emit(Instruction::Label(*start));
+                    self.emit(Instruction::PermutationClaim(i));
+
+                    self.compile_element(c)?;
+
+                    self.emit(Instruction::Jump(JumpTarget::Symbolic(loop_label)));
+                }
+
+                self.emit(Instruction::Label(done_label));
+                self.emit(Instruction::PermutationEnd {
+                    all_required: all_required,
+                    count: children.len(),
+                });
+            }
             Element::Repetition { ref child } => {
                 let loop_label = self.new_label();
                 let done_label = self.new_label();
@@ -142,12 +307,57 @@ impl<'a> Compiler<'a> {
 
                 self.emit(Instruction::Progress);
 
-                self.compile_element(child);
+                self.compile_element(child)?;
 
                 self.emit(make_split(&[loop_label, done_label]));
 
                 self.emit(Instruction::Label(done_label));
             }
+            Element::SeparatedList {
+                ref child,
+                ref separator,
+                ref last_separator,
+            } => {
+                let item_label = self.new_label();
+                let start_label = self.new_label();
+                let loop_label = self.new_label();
+                let more_label = self.new_label();
+                let last_label = self.new_label();
+                let done_label = self.new_label();
+
+                // the child is compiled once, as a subroutine that every
+                // item calls, so nested lists do not grow exponentially
+                self.emit(Instruction::Jump(JumpTarget::Symbolic(start_label)));
+                self.emit(Instruction::Label(item_label));
+                self.compile_element(child)?;
+                self.emit(Instruction::Return);
+
+                self.emit(Instruction::Label(start_label));
+                self.emit(Instruction::RuleCall(JumpTarget::Symbolic(item_label)));
+
+                self.emit(Instruction::Label(loop_label));
+                self.emit(Instruction::Progress);
+
+                // greedy since done label is put last
+                if last_separator.is_some() {
+                    self.emit(make_split(&[more_label, last_label, done_label]));
+                } else {
+                    self.emit(make_split(&[more_label, done_label]));
+                }
+
+                self.emit(Instruction::Label(more_label));
+                self.compile_separator(separator)?;
+                self.emit(Instruction::RuleCall(JumpTarget::Symbolic(item_label)));
+                self.emit(Instruction::Jump(JumpTarget::Symbolic(loop_label)));
+
+                if let Some(ref last) = *last_separator {
+                    self.emit(Instruction::Label(last_label));
+                    self.compile_separator(last)?;
+                    self.emit(Instruction::RuleCall(JumpTarget::Symbolic(item_label)));
+                }
+
+                self.emit(Instruction::Label(done_label));
+            }
             Element::Optional { ref child } => {
                 let yes_label = self.new_label();
                 let no_label = self.new_label();
@@ -155,7 +365,7 @@ impl<'a> Compiler<'a> {
                 self.emit(make_split(&[yes_label, no_label]));
                 self.emit(Instruction::Label(yes_label));
 
-                self.compile_element(child);
+                self.compile_element(child)?;
 
                 self.emit(Instruction::Label(no_label));
             }
@@ -164,7 +374,7 @@ impl<'a> Compiler<'a> {
                 ref child,
             } => {
                 self.emit(Instruction::CaptureStart(name.clone()));
-                self.compile_element(child);
+                self.compile_element(child)?;
                 self.emit(Instruction::CaptureStop);
             }
             Element::Word { ref text } => {
@@ -172,27 +382,162 @@ impl<'a> Compiler<'a> {
             }
             Element::RuleRef { ref name } => {
                 let name: &'a str = name;
-                let target = JumpTarget::Symbolic(self.rule_name_to_label[name]);
-                self.emit(Instruction::RuleCall(target));
+                let label = *self.rule_name_to_label.get(name).ok_or_else(|| {
+                    MatcherError::UnknownRule {
+                        name: name.to_owned(),
+                    }
+                })?;
+                let target = JumpTarget::Symbolic(label);
+                if self.left_recursive.contains(name) {
+                    self.emit(Instruction::GuardedCall(target));
+                    self.emit(Instruction::GuardRelease);
+                } else {
+                    self.emit(Instruction::RuleCall(target));
+                }
             }
-            Element::List { .. } | Element::DictationWord | Element::SpellingLetter => {
-                self.emit(Instruction::AnyWord);
+            Element::List { ref name } => {
+                self.emit(Instruction::List(name.clone()));
             }
-            Element::Dictation => {
-                let loop_label = self.new_label();
-                let done_label = self.new_label();
+            Element::SpellingLetter => {
+                self.emit(Instruction::SpellingLetter);
+            }
+            Element::DictationWord { exclude_keywords } => {
+                self.emit(Instruction::DictationStart);
+                if exclude_keywords {
+                    self.emit(Instruction::AnyWordExcept(self.keywords.clone()));
+                } else {
+                    self.emit(Instruction::AnyWord { dictation: true });
+                }
+                self.emit(Instruction::DictationStop);
+            }
+            Element::Import { ref name } => {
+                let placeholder = self
+                    .placeholders
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_else(|| default_placeholder(name));
+
+                match placeholder {
+                    Placeholder::Word => self.emit(Instruction::AnyWord { dictation: false }),
+                    Placeholder::Words => self.compile_words(1, None, None, false, false)?,
+                }
+            }
+            Element::Dictation {
+                min_words,
+                max_words,
+                ref stop_words,
+                greedy,
+            } => {
+                let stop_words = if stop_words.is_empty() {
+                    None
+                } else {
+                    Some(Arc::new(stop_words.iter().cloned().collect()))
+                };
+
+                self.emit(Instruction::DictationStart);
+                self.compile_words(min_words.unwrap_or(1), max_words, stop_words, greedy, true)?;
+                self.emit(Instruction::DictationStop);
+            }
+        }
 
-                self.emit(Instruction::Label(loop_label));
+        Ok(())
+    }
 
-                self.emit(Instruction::Progress);
+    fn compile_word(&mut self, stop_words: &Option<Arc<HashSet<String>>>, dictation: bool) {
+        match *stop_words {
+            Some(ref words) => self.emit(Instruction::AnyWordExcept(words.clone())),
+            None => self.emit(Instruction::AnyWord {
+                dictation: dictation,
+            }),
+        }
+    }
 
-                self.emit(Instruction::AnyWord);
+    fn compile_words(
+        &mut self,
+        min_words: usize,
+        max_words: Option<usize>,
+        stop_words: Option<Arc<HashSet<String>>>,
+        greedy: bool,
+        dictation: bool,
+    ) -> Result<()> {
+        for &limit in [Some(min_words), max_words].iter().flatten() {
+            if limit > MAX_DICTATION_WORDS {
+                return Err(MatcherError::WordLimitTooLarge {
+                    limit: limit,
+                    max: MAX_DICTATION_WORDS,
+                });
+            }
+        }
 
-                // non-greedy since done label is put first
-                self.emit(make_split(&[done_label, loop_label]));
+        if let Some(max_words) = max_words {
+            if min_words > max_words {
+                return Err(MatcherError::InvalidWordLimits {
+                    min: min_words,
+                    max: max_words,
+                });
+            }
+        }
 
-                self.emit(Instruction::Label(done_label));
+        let loop_label = self.new_label();
+        let more_label = self.new_label();
+        let done_label = self.new_label();
+
+        let choice = |more, done| {
+            if greedy {
+                make_split(&[more, done])
+            } else {
+                make_split(&[done, more])
             }
+        };
+
+        for _ in 0..min_words {
+            self.compile_word(&stop_words, dictation);
+        }
+
+        // the optional words are a loop, which counts its iterations if
+        // there is a maximum
+        let tail = max_words.map(|max_words| max_words - min_words);
+        if tail == Some(0) {
+            return Ok(());
+        }
+
+        if tail.is_some() {
+            self.emit(Instruction::CounterStart);
+        }
+
+        self.emit(Instruction::Label(loop_label));
+
+        self.emit(Instruction::Progress);
+
+        self.emit(choice(more_label, done_label));
+        self.emit(Instruction::Label(more_label));
+
+        if let Some(tail) = tail {
+            self.emit(Instruction::CounterBelow(tail));
         }
+
+        self.compile_word(&stop_words, dictation);
+
+        if tail.is_some() {
+            self.emit(Instruction::CounterIncrement);
+        }
+
+        self.emit(Instruction::Jump(JumpTarget::Symbolic(loop_label)));
+
+        self.emit(Instruction::Label(done_label));
+
+        if tail.is_some() {
+            self.emit(Instruction::CounterStop);
+        }
+
+        Ok(())
+    }
+
+    // separators do not show up in the captures
+    fn compile_separator(&mut self, separator: &'a Element) -> Result<()> {
+        self.emit(Instruction::SuppressStart);
+        self.compile_element(separator)?;
+        self.emit(Instruction::SuppressStop);
+        Ok(())
     }
 }
@@ -0,0 +1,495 @@
+use super::captures::Match;
+use crate::engine::WordInfo;
+use serde::de::value::{StrDeserializer, StringDeserializer};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use std::vec;
+
+#[derive(Debug, Clone)]
+pub struct DeError(String);
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeError(msg.to_string())
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, DeError>;
+
+pub fn from_match<'a, T>(captures: &'a [Match<'a>], words: &'a [WordInfo]) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(MatchDeserializer::new(captures, words))
+}
+
+/// Deserializes the captures of a match. Every capture acts as a
+/// struct whose fields are its child captures, while a capture
+/// without children is parsed from the words it spans, separated by
+/// spaces. The top level behaves like a capture spanning all of the
+/// words.
+#[derive(Debug, Copy, Clone)]
+pub struct MatchDeserializer<'a> {
+    name: &'a str,
+    slice: (usize, usize),
+    children: &'a [Match<'a>],
+    words: &'a [WordInfo],
+}
+
+impl<'a> MatchDeserializer<'a> {
+    pub fn new(captures: &'a [Match<'a>], words: &'a [WordInfo]) -> Self {
+        MatchDeserializer {
+            name: "",
+            slice: (0, words.len()),
+            children: captures,
+            words: words,
+        }
+    }
+
+    fn from_capture(capture: &'a Match<'a>, words: &'a [WordInfo]) -> Self {
+        MatchDeserializer {
+            name: capture.name,
+            slice: capture.slice,
+            children: &capture.children,
+            words: words,
+        }
+    }
+
+    fn text(&self) -> Result<String> {
+        let (start, stop) = self.slice;
+        let words = self.words.get(start..stop).ok_or_else(|| {
+            DeError(format!(
+                "capture {} refers to words outside of the utterance",
+                self.name
+            ))
+        })?;
+
+        let texts = words.iter().map(|w| &w.text as &str).collect::<Vec<_>>();
+        Ok(texts.join(" "))
+    }
+
+    fn parse<T>(&self) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let text = self.text()?;
+        text.parse().map_err(|e| {
+            DeError(format!(
+                "cannot parse capture {} from \"{}\": {}",
+                self.name, text, e
+            ))
+        })
+    }
+
+    fn fields(&self, declared: &'static [&'static str]) -> CaptureMap<'a> {
+        let mut fields: Vec<(&'a str, Vec<&'a Match<'a>>)> = Vec::new();
+
+        for c in self.children.iter() {
+            if let Some(entry) = fields.iter_mut().find(|&&mut (n, _)| n == c.name) {
+                entry.1.push(c);
+                continue;
+            }
+
+            fields.push((c.name, vec![c]));
+        }
+
+        // declared fields without any captures still get a value so
+        // that they can become None or an empty Vec
+        for &d in declared.iter() {
+            if !fields.iter().any(|&(n, _)| n == d) {
+                fields.push((d, Vec::new()));
+            }
+        }
+
+        CaptureMap {
+            fields: fields.into_iter(),
+            value: None,
+            words: self.words,
+        }
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for MatchDeserializer<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.children.is_empty() {
+            visitor.visit_string(self.text()?)
+        } else {
+            visitor.visit_map(self.fields(&[]))
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.text()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.text()?)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.text()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // a single capture used as a sequence yields its individual words
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let (start, stop) = self.slice;
+        let words = self.words.get(start..stop).unwrap_or(&[]);
+        visitor.visit_seq(WordSeq {
+            words: words.iter(),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_map(self.fields(&[]))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_map(self.fields(fields))
+    }
+
+    // the variant is selected by the name of the first child capture,
+    // or by the words themselves if there are no child captures
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let access = if let Some(first) = self.children.first() {
+            CaptureEnum {
+                variant: first.name.to_owned(),
+                content: Some(MatchDeserializer::from_capture(first, self.words)),
+            }
+        } else {
+            CaptureEnum {
+                variant: self.text()?,
+                content: None,
+            }
+        };
+
+        visitor.visit_enum(access)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 bytes byte_buf
+    }
+}
+
+/// All captures with the same name below a single parent. Sequences
+/// deserialize every capture, options check whether there is any
+/// capture at all and everything else requires exactly one capture.
+struct CaptureGroup<'a> {
+    name: &'a str,
+    captures: Vec<&'a Match<'a>>,
+    words: &'a [WordInfo],
+}
+
+impl<'a> CaptureGroup<'a> {
+    fn single(self) -> Result<MatchDeserializer<'a>> {
+        match self.captures.len() {
+            0 => Err(DeError(format!("missing capture {}", self.name))),
+            1 => Ok(MatchDeserializer::from_capture(
+                self.captures[0],
+                self.words,
+            )),
+            n => Err(DeError(format!(
+                "expected capture {} once, but found it {} times",
+                self.name, n
+            ))),
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident($($arg:ident: $ty:ty),*),)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value> {
+                self.single()?.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for CaptureGroup<'a> {
+    type Error = DeError;
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.captures.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(CaptureSeq {
+            captures: self.captures.into_iter(),
+            words: self.words,
+        })
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    forward_to_single! {
+        deserialize_any(),
+        deserialize_bool(),
+        deserialize_i8(),
+        deserialize_i16(),
+        deserialize_i32(),
+        deserialize_i64(),
+        deserialize_i128(),
+        deserialize_u8(),
+        deserialize_u16(),
+        deserialize_u32(),
+        deserialize_u64(),
+        deserialize_u128(),
+        deserialize_f32(),
+        deserialize_f64(),
+        deserialize_char(),
+        deserialize_str(),
+        deserialize_string(),
+        deserialize_bytes(),
+        deserialize_byte_buf(),
+        deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str),
+        deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize),
+        deserialize_map(),
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(),
+    }
+}
+
+struct CaptureMap<'a> {
+    fields: vec::IntoIter<(&'a str, Vec<&'a Match<'a>>)>,
+    value: Option<CaptureGroup<'a>>,
+    words: &'a [WordInfo],
+}
+
+impl<'de, 'a> MapAccess<'de> for CaptureMap<'a> {
+    type Error = DeError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if let Some((name, captures)) = self.fields.next() {
+            self.value = Some(CaptureGroup {
+                name: name,
+                captures: captures,
+                words: self.words,
+            });
+
+            let key: StrDeserializer<DeError> = name.into_deserializer();
+            seed.deserialize(key).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let group = self
+            .value
+            .take()
+            .ok_or_else(|| DeError("value requested before key".to_owned()))?;
+        seed.deserialize(group)
+    }
+}
+
+struct CaptureSeq<'a> {
+    captures: vec::IntoIter<&'a Match<'a>>,
+    words: &'a [WordInfo],
+}
+
+impl<'de, 'a> SeqAccess<'de> for CaptureSeq<'a> {
+    type Error = DeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if let Some(c) = self.captures.next() {
+            let element = MatchDeserializer::from_capture(c, self.words);
+            seed.deserialize(element).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+struct WordSeq<'a> {
+    words: ::std::slice::Iter<'a, WordInfo>,
+}
+
+impl<'de, 'a> SeqAccess<'de> for WordSeq<'a> {
+    type Error = DeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if let Some(w) = self.words.next() {
+            let element: StrDeserializer<DeError> = (&w.text as &str).into_deserializer();
+            seed.deserialize(element).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+struct CaptureEnum<'a> {
+    variant: String,
+    content: Option<MatchDeserializer<'a>>,
+}
+
+impl<'de, 'a> EnumAccess<'de> for CaptureEnum<'a> {
+    type Error = DeError;
+    type Variant = CaptureVariant<'a>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant: StringDeserializer<DeError> = self.variant.into_deserializer();
+        let value = seed.deserialize(variant)?;
+        Ok((
+            value,
+            CaptureVariant {
+                content: self.content,
+            },
+        ))
+    }
+}
+
+struct CaptureVariant<'a> {
+    content: Option<MatchDeserializer<'a>>,
+}
+
+impl<'a> CaptureVariant<'a> {
+    fn content(self) -> Result<MatchDeserializer<'a>> {
+        self.content
+            .ok_or_else(|| DeError("enum variant requires a child capture".to_owned()))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for CaptureVariant<'a> {
+    type Error = DeError;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.content()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self.content()?, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_struct(self.content()?, "", fields, visitor)
+    }
+}
@@ -0,0 +1,397 @@
+//! Approximate matching, for utterances that were not recognized with
+//! the grammar itself, such as dictation that was meant as a command.
+//!
+//! This is a shortest path search over the states of the Pike VM, where
+//! each step either matches the next word or makes an edit, and the
+//! parse with the lowest total cost of its edits wins. Parses of the same
+//! cost are ordered like the threads of the Pike VM.
+
+use super::budget::{Budget, MatchBudget};
+use super::captures::Match;
+use super::instructions::Instruction;
+use super::lists::Lists;
+use super::pikevm::{
+    advance, continuation, matches_word, replay_captures, spelled_letter, CaptureEvent, CaptureLog,
+    Thread, ThreadList,
+};
+use super::prediction::Continuation;
+use super::vm::{fetch, invalid, Result};
+use super::MatchError;
+use crate::engine::WordInfo;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::mem;
+use std::rc::Rc;
+
+/// The costs of the edits needed to make an utterance match. Costs must
+/// not be negative or NaN, otherwise matching fails with
+/// `MatchError::InvalidProgram`.
+pub trait EditCosts {
+    /// Taking `actual` as the grammar word `expected`, for instance
+    /// because they sound alike.
+    fn substitution(&self, _expected: &str, _actual: &str) -> f64 {
+        1.0
+    }
+
+    /// Ignoring a word of the utterance.
+    fn insertion(&self, _actual: &str) -> f64 {
+        1.0
+    }
+
+    /// Assuming a word the grammar expects was left out.
+    fn deletion(&self, _expected: &Continuation) -> f64 {
+        1.0
+    }
+}
+
+/// Costs every edit the same.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct UniformCosts;
+
+impl EditCosts for UniformCosts {}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Edit<'a> {
+    /// The word at `position` was ignored.
+    Insertion { position: usize, actual: String },
+    /// The grammar expected a word before `position` that is not there.
+    Deletion {
+        position: usize,
+        expected: Continuation<'a>,
+    },
+    /// The word at `position` was taken as the word the grammar expected.
+    Substitution {
+        position: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FuzzyMatch<'a> {
+    pub captures: Vec<Match<'a>>,
+    /// The edits in the order of the utterance.
+    pub edits: Vec<Edit<'a>>,
+    pub cost: f64,
+}
+
+struct EditLog<'a> {
+    edit: Edit<'a>,
+    previous: Option<Rc<EditLog<'a>>>,
+}
+
+fn log_edit<'a>(edits: &Option<Rc<EditLog<'a>>>, edit: Edit<'a>) -> Option<Rc<EditLog<'a>>> {
+    Some(Rc::new(EditLog {
+        edit: edit,
+        previous: edits.clone(),
+    }))
+}
+
+fn collect_edits<'a>(log: &Option<Rc<EditLog<'a>>>) -> Vec<Edit<'a>> {
+    let mut edits = Vec::new();
+    let mut current = log.as_ref();
+    while let Some(entry) = current {
+        edits.push(entry.edit.clone());
+        current = entry.previous.as_ref();
+    }
+
+    edits.reverse();
+    edits
+}
+
+enum Step<'a> {
+    /// A thread that still has to follow the instructions that do not
+    /// consume a word.
+    Thread(Thread<'a>),
+    /// A thread that matched, along with its captures.
+    Done(Option<Rc<CaptureLog<'a>>>),
+}
+
+struct Node<'a> {
+    cost: f64,
+    // breaks ties in the order the nodes were found
+    order: u64,
+    position: usize,
+    step: Step<'a>,
+    edits: Option<Rc<EditLog<'a>>>,
+}
+
+impl<'a> PartialEq for Node<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for Node<'a> {}
+
+impl<'a> PartialOrd for Node<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// reversed, since the binary heap pops the greatest node first. The
+// costs are checked, so they are never NaN.
+impl<'a> Ord for Node<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let by_cost = other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal);
+
+        by_cost.then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+struct Search<'a, 'c> {
+    program: &'a [Instruction],
+    lists: &'c Lists,
+    string: &'c [WordInfo],
+    costs: &'c dyn EditCosts,
+    queue: BinaryHeap<Node<'a>>,
+    order: u64,
+}
+
+impl<'a, 'c> Search<'a, 'c> {
+    fn push(&mut self, cost: f64, position: usize, step: Step<'a>, edits: Option<Rc<EditLog<'a>>>) {
+        self.order += 1;
+        self.queue.push(Node {
+            cost: cost,
+            order: self.order,
+            position: position,
+            step: step,
+            edits: edits,
+        });
+    }
+
+    // the shortest path search relies on costs never decreasing along a
+    // path, and NaN can not be ordered at all
+    fn check(cost: f64) -> Result<f64> {
+        if cost >= 0.0 {
+            Ok(cost)
+        } else {
+            invalid(&format!("edit cost {} is negative or NaN", cost))
+        }
+    }
+
+    fn substitution(&self, expected: &str, actual: &str) -> Result<f64> {
+        Search::check(self.costs.substitution(expected, actual))
+    }
+
+    fn insertion(&self, actual: &str) -> Result<f64> {
+        Search::check(self.costs.insertion(actual))
+    }
+
+    fn deletion(&self, expected: &Continuation) -> Result<f64> {
+        Search::check(self.costs.deletion(expected))
+    }
+
+    // ignoring all the remaining words
+    fn finish(&mut self, node: &Node<'a>, captures: Option<Rc<CaptureLog<'a>>>) -> Result<()> {
+        let mut cost = node.cost;
+        let mut edits = node.edits.clone();
+        for (position, word) in self.string.iter().enumerate().skip(node.position) {
+            cost += self.insertion(&word.text)?;
+            edits = log_edit(
+                &edits,
+                Edit::Insertion {
+                    position: position,
+                    actual: word.text.clone(),
+                },
+            );
+        }
+
+        self.push(cost, self.string.len(), Step::Done(captures), edits);
+        Ok(())
+    }
+
+    /// Makes all possible steps from a thread waiting for a word.
+    fn expand(&mut self, node: &Node<'a>, t: Thread<'a>) -> Result<()> {
+        let position = node.position;
+        let instruction = fetch(self.program, t.state.program_pointer)?;
+
+        // every entry of a list is tried, as if it was an alternative
+        if let Instruction::List(ref name) = *instruction {
+            let entries = self.lists.get(name);
+            if let (None, Some(entries)) = (t.state.list_entry, entries) {
+                let mut chosen_any = false;
+                for (index, entry) in entries.iter().enumerate() {
+                    if !entry.words.is_empty() {
+                        let mut chosen = t.clone();
+                        chosen.choose_entry(index, position);
+                        self.expand(node, chosen)?;
+                        chosen_any = true;
+                    }
+                }
+
+                // an empty list can only be left out as a whole
+                if !chosen_any {
+                    let mut skipped = t;
+                    skipped.state.program_pointer += 1;
+
+                    let expected = Continuation::List(name);
+                    let cost = node.cost + self.deletion(&expected)?;
+                    let edit = Edit::Deletion {
+                        position: position,
+                        expected: expected,
+                    };
+                    self.push(
+                        cost,
+                        position,
+                        Step::Thread(skipped),
+                        log_edit(&node.edits, edit),
+                    );
+                }
+
+                return Ok(());
+            }
+        }
+
+        let expected = continuation(self.program, self.lists, &t.state)?;
+
+        if let Some(word) = self.string.get(position) {
+            let actual = &word.text;
+
+            let matches = match expected {
+                Continuation::Word(ref w) => w == actual,
+                _ => matches_word(instruction, self.lists, actual),
+            };
+
+            if matches {
+                let mut advanced = t.clone();
+                if let Instruction::SpellingLetter = *instruction {
+                    if advanced.state.suppressed == 0 {
+                        let letter = spelled_letter(actual)?;
+                        advanced.log(CaptureEvent::Letter(position, letter));
+                    }
+                }
+
+                advanced.state.progress.clear();
+                advance(self.program, self.lists, &mut advanced, position)?;
+                self.push(
+                    node.cost,
+                    position + 1,
+                    Step::Thread(advanced),
+                    node.edits.clone(),
+                );
+            } else if let Continuation::Word(ref w) = expected {
+                let mut advanced = t.clone();
+                advanced.state.progress.clear();
+                advance(self.program, self.lists, &mut advanced, position)?;
+
+                let edit = Edit::Substitution {
+                    position: position,
+                    expected: w.clone(),
+                    actual: actual.clone(),
+                };
+                self.push(
+                    node.cost + self.substitution(w, actual)?,
+                    position + 1,
+                    Step::Thread(advanced),
+                    log_edit(&node.edits, edit),
+                );
+            }
+
+            let mut ignored = t.clone();
+            ignored.state.progress.clear();
+
+            let edit = Edit::Insertion {
+                position: position,
+                actual: actual.clone(),
+            };
+            self.push(
+                node.cost + self.insertion(actual)?,
+                position + 1,
+                Step::Thread(ignored),
+                log_edit(&node.edits, edit),
+            );
+        }
+
+        let mut skipped = t;
+        advance(self.program, self.lists, &mut skipped, position)?;
+
+        let cost = node.cost + self.deletion(&expected)?;
+        let edit = Edit::Deletion {
+            position: position,
+            expected: expected,
+        };
+        self.push(
+            cost,
+            position,
+            Step::Thread(skipped),
+            log_edit(&node.edits, edit),
+        );
+
+        Ok(())
+    }
+}
+
+pub fn perform_fuzzy_match<'a>(
+    program: &'a [Instruction],
+    lists: &Lists,
+    string: &[WordInfo],
+    costs: &dyn EditCosts,
+    budget: &MatchBudget,
+) -> Result<FuzzyMatch<'a>> {
+    let mut budget = Budget::new(budget);
+
+    // each position has its own thread list, so a state that has been
+    // reached at a position is never followed again at a higher cost
+    let mut positions = (0..string.len() + 1)
+        .map(|p| ThreadList::new(program, p, string.len()))
+        .collect::<Vec<_>>();
+
+    let mut search = Search {
+        program: program,
+        lists: lists,
+        string: string,
+        costs: costs,
+        queue: BinaryHeap::new(),
+        order: 0,
+    };
+    search.push(0.0, 0, Step::Thread(Thread::new(0)), None);
+
+    while let Some(mut node) = search.queue.pop() {
+        budget.step()?;
+
+        let t = match mem::replace(&mut node.step, Step::Done(None)) {
+            Step::Thread(t) => t,
+            Step::Done(captures) => {
+                return Ok(FuzzyMatch {
+                    captures: replay_captures(&captures, lists)?,
+                    edits: collect_edits(&node.edits),
+                    cost: node.cost,
+                });
+            }
+        };
+
+        let threads = &mut positions[node.position];
+        threads.add(t, &mut budget)?;
+
+        let waiting = mem::replace(&mut threads.threads, Vec::new());
+        let matched = threads.matched.take();
+        let returned = threads.returned.take();
+
+        if let Some(captures) = matched {
+            search.push(
+                node.cost,
+                node.position,
+                Step::Done(captures),
+                node.edits.clone(),
+            );
+        }
+
+        if let Some(captures) = returned {
+            search.finish(&node, captures)?;
+        }
+
+        for t in waiting.into_iter() {
+            budget.step()?;
+            search.expand(&node, t)?;
+        }
+    }
+
+    Err(MatchError::NoMatch)
+}
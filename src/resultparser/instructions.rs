@@ -1,36 +1,86 @@
-#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct LabelName(pub u32);
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum JumpTarget {
     Symbolic(LabelName),
     Concrete(usize),
 }
 
 impl JumpTarget {
-    pub fn address(&self) -> usize {
+    /// Returns `None` for symbolic targets, which should have been
+    /// resolved by the compiler.
+    pub fn address(&self) -> Option<usize> {
         if let JumpTarget::Concrete(address) = *self {
-            address
+            Some(address)
         } else {
-            panic!("found symbolic jump instruction");
+            None
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// A child taken in one of the alternatives of a rule. The alternatives
+/// of a rule are numbered in the order they appear in its definition.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Choice {
+    pub rule: String,
+    pub alternative: usize,
+    pub child: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Instruction {
     Literal(String),
-    AnyWord,
+    /// Any word, either as part of dictation or for an imported rule.
+    AnyWord {
+        dictation: bool,
+    },
+    /// Any word of dictation that is not in the set, which is shared
+    /// between all instructions that exclude the same words.
+    AnyWordExcept(Arc<HashSet<String>>),
+    /// Any entry of the named list, which may be more than one word.
+    List(String),
+    SpellingLetter,
 
     Label(LabelName),
     NoOp,
 
     Progress,
 
+    PermutationStart,
+    PermutationClaim(usize),
+    PermutationEnd {
+        all_required: bool,
+        count: usize,
+    },
+
+    /// Starts counting the iterations of a loop.
+    CounterStart,
+    /// Fails if the loop has been taken at least this many times.
+    CounterBelow(usize),
+    CounterIncrement,
+    CounterStop,
+
     CaptureStart(String),
     CaptureStop,
+    SuppressStart,
+    SuppressStop,
+
+    /// Records which child of an alternative was taken.
+    Choice(Choice),
+    DictationStart,
+    DictationStop,
 
     RuleCall(JumpTarget),
+    /// Calls a left-recursive rule, unless it is already being called at
+    /// the current position more often than there are words left.
+    GuardedCall(JumpTarget),
+    /// Follows a `GuardedCall`, which is where its rule returns to.
+    GuardRelease,
     Return,
     Jump(JumpTarget),
     Split(Box<[JumpTarget]>),
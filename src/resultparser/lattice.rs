@@ -0,0 +1,224 @@
+//! Matching against the alternatives Dragon delivers for an utterance,
+//! either as a list of choices or as a lattice of words.
+
+use super::budget::{Budget, MatchBudget};
+use super::captures::Match;
+use super::instructions::Instruction;
+use super::lists::Lists;
+use super::pikevm;
+use super::vm::Result;
+use super::MatchError;
+use crate::engine::{WordInfo, Words};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Alternative words for an utterance, where the words at every path
+/// from one of the `starts` to a node without any `next` nodes are a
+/// possible recognition.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Lattice {
+    pub nodes: Vec<LatticeNode>,
+    pub starts: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatticeNode {
+    pub word: WordInfo,
+    /// Higher scores are better.
+    pub score: u32,
+    pub next: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChoiceMatch<'a> {
+    pub captures: Vec<Match<'a>>,
+    /// The index of the choice that matched.
+    pub choice: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatticeMatch<'a> {
+    pub captures: Vec<Match<'a>>,
+    /// The nodes of the path that matched, which the captures refer to.
+    pub path: Vec<usize>,
+    /// The sum of the scores of the nodes on the path.
+    pub score: u64,
+}
+
+/// Matches the first of the choices that matches, since Dragon orders
+/// them from best to worst.
+pub fn perform_match_choices<'a>(
+    program: &'a [Instruction],
+    lists: &Lists,
+    choices: &[Words],
+    budget: &MatchBudget,
+) -> Result<ChoiceMatch<'a>> {
+    let mut budget = Budget::new(budget);
+
+    for (i, words) in choices.iter().enumerate() {
+        match pikevm::perform_match(program, lists, words, &mut budget) {
+            Ok(captures) => {
+                return Ok(ChoiceMatch {
+                    captures: captures,
+                    choice: i,
+                });
+            }
+            Err(MatchError::NoMatch) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(MatchError::NoMatch)
+}
+
+struct Path {
+    // the score of the path so far, plus the best score it can still get
+    bound: u64,
+    score: u64,
+    nodes: Vec<usize>,
+    // whether the path can not be extended, so it is matched when it is
+    // taken from the queue
+    complete: bool,
+}
+
+impl PartialEq for Path {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Path {}
+
+impl PartialOrd for Path {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// ties go to the shorter path, so paths are completed in a stable order
+impl Ord for Path {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bound
+            .cmp(&other.bound)
+            .then_with(|| other.nodes.len().cmp(&self.nodes.len()))
+            .then_with(|| other.nodes.cmp(&self.nodes))
+    }
+}
+
+/// Matches the paths through the lattice from the highest score to the
+/// lowest, until one of them matches.
+pub fn perform_match_lattice<'a>(
+    program: &'a [Instruction],
+    lists: &Lists,
+    lattice: &Lattice,
+    budget: &MatchBudget,
+) -> Result<LatticeMatch<'a>> {
+    let mut budget = Budget::new(budget);
+    let best = best_completions(lattice);
+
+    let mut queue = BinaryHeap::new();
+    for &start in lattice.starts.iter() {
+        if let Some(node) = lattice.nodes.get(start) {
+            queue.push(Path {
+                bound: best[start],
+                score: u64::from(node.score),
+                nodes: vec![start],
+                complete: false,
+            });
+        }
+    }
+
+    while let Some(path) = queue.pop() {
+        budget.step()?;
+
+        if !path.complete {
+            let last = match path.nodes.last().and_then(|&n| lattice.nodes.get(n)) {
+                Some(last) => last,
+                None => continue,
+            };
+
+            // nodes that would make the path go around in circles are left
+            // out, and a path that can not go anywhere else is complete
+            let next = last
+                .next
+                .iter()
+                .cloned()
+                .filter(|&n| n < lattice.nodes.len() && !path.nodes.contains(&n))
+                .collect::<Vec<_>>();
+
+            if next.is_empty() {
+                // its bound may have counted nodes that were left out
+                queue.push(Path {
+                    bound: path.score,
+                    complete: true,
+                    ..path
+                });
+                continue;
+            }
+
+            for next in next {
+                let mut nodes = path.nodes.clone();
+                nodes.push(next);
+                queue.push(Path {
+                    bound: path.score + best[next],
+                    score: path.score + u64::from(lattice.nodes[next].score),
+                    nodes: nodes,
+                    complete: false,
+                });
+            }
+
+            continue;
+        }
+
+        let words = path
+            .nodes
+            .iter()
+            .map(|&n| lattice.nodes[n].word.clone())
+            .collect::<Vec<_>>();
+
+        match pikevm::perform_match(program, lists, &words, &mut budget) {
+            Ok(captures) => {
+                return Ok(LatticeMatch {
+                    captures: captures,
+                    path: path.nodes,
+                    score: path.score,
+                });
+            }
+            Err(MatchError::NoMatch) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(MatchError::NoMatch)
+}
+
+/// The highest score of a path from each node to the end, including the
+/// node itself.
+fn best_completions(lattice: &Lattice) -> Vec<u64> {
+    fn visit(lattice: &Lattice, node: usize, best: &mut Vec<Option<u64>>) -> u64 {
+        if let Some(score) = best[node] {
+            return score;
+        }
+
+        // guards against cycles, which should not be there
+        best[node] = Some(0);
+
+        let completion = lattice.nodes[node]
+            .next
+            .iter()
+            .filter(|&&n| n < lattice.nodes.len())
+            .map(|&n| visit(lattice, n, best))
+            .max()
+            .unwrap_or(0);
+
+        let score = u64::from(lattice.nodes[node].score) + completion;
+        best[node] = Some(score);
+        score
+    }
+
+    let mut best = vec![None; lattice.nodes.len()];
+    (0..lattice.nodes.len())
+        .map(|n| visit(lattice, n, &mut best))
+        .collect()
+}
@@ -0,0 +1,183 @@
+//! Detection of left recursion, where a rule can end up calling itself
+//! without matching any words in between. The matcher would recurse
+//! forever on such rules, unless the calls are guarded (see
+//! `MatcherOptions::left_recursion`).
+
+use crate::grammar::{Element, Grammar};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+pub struct LeftRecursion<'a> {
+    /// All rules that are part of a left-recursive cycle.
+    pub rules: HashSet<&'a str>,
+    /// The first cycle that was found, starting and ending with the same
+    /// rule.
+    pub cycle: Option<Vec<&'a str>>,
+}
+
+pub fn find_left_recursion<'a>(grammar: &'a Grammar) -> LeftRecursion<'a> {
+    let nullable = nullable_rules(grammar);
+    let analysis = Analysis {
+        nullable: &nullable,
+    };
+
+    let mut edges: HashMap<&'a str, Vec<&'a str>> = HashMap::new();
+    for r in grammar.rules.iter() {
+        let mut called = Vec::new();
+        analysis.left_calls(&r.definition, &mut called);
+        edges.entry(&r.name).or_insert_with(Vec::new).extend(called);
+    }
+
+    let mut rules = HashSet::new();
+    let mut cycle = None;
+    for r in grammar.rules.iter() {
+        if let Some(path) = path_to_self(&r.name, &edges) {
+            rules.insert(&r.name as &str);
+            if cycle.is_none() {
+                cycle = Some(path);
+            }
+        }
+    }
+
+    LeftRecursion {
+        rules: rules,
+        cycle: cycle,
+    }
+}
+
+fn path_to_self<'a>(
+    start: &'a str,
+    edges: &HashMap<&'a str, Vec<&'a str>>,
+) -> Option<Vec<&'a str>> {
+    let mut parents: HashMap<&'a str, &'a str> = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+
+    while let Some(current) = queue.pop_front() {
+        for &next in edges.get(current).map_or(&[][..], |e| &e[..]) {
+            if next == start {
+                let mut path = vec![start];
+                let mut node = current;
+                while node != start {
+                    path.push(node);
+                    node = parents[node];
+                }
+                path.push(start);
+                path.reverse();
+                return Some(path);
+            }
+
+            if !parents.contains_key(next) {
+                parents.insert(next, current);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+fn nullable_rules<'a>(grammar: &'a Grammar) -> HashSet<&'a str> {
+    let mut nullable = HashSet::new();
+
+    // rules can only become nullable, so repeat until nothing changes
+    loop {
+        let mut changed = false;
+
+        for r in grammar.rules.iter() {
+            if nullable.contains(&r.name as &str) {
+                continue;
+            }
+
+            let analysis = Analysis {
+                nullable: &nullable,
+            };
+            if analysis.is_nullable(&r.definition) {
+                nullable.insert(&r.name as &str);
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return nullable;
+        }
+    }
+}
+
+struct Analysis<'a, 'b> {
+    nullable: &'b HashSet<&'a str>,
+}
+
+impl<'a, 'b> Analysis<'a, 'b> {
+    /// Whether the element can match without matching any words.
+    fn is_nullable(&self, element: &Element) -> bool {
+        match *element {
+            Element::Sequence { ref children } => children.iter().all(|c| self.is_nullable(c)),
+            Element::Alternative { ref children } => children.iter().any(|c| self.is_nullable(c)),
+            Element::Permutation {
+                ref children,
+                all_required,
+            } => {
+                if all_required || children.is_empty() {
+                    children.iter().all(|c| self.is_nullable(c))
+                } else {
+                    children.iter().any(|c| self.is_nullable(c))
+                }
+            }
+            Element::Repetition { ref child }
+            | Element::Capture { ref child, .. }
+            | Element::SeparatedList { ref child, .. } => self.is_nullable(child),
+            Element::Optional { .. } => true,
+            Element::RuleRef { ref name } => self.nullable.contains(name as &str),
+            Element::Dictation { min_words, .. } => min_words == Some(0),
+            // placeholders for imported rules match at least one word
+            Element::Import { .. }
+            | Element::Word { .. }
+            | Element::List { .. }
+            | Element::DictationWord { .. }
+            | Element::SpellingLetter => false,
+        }
+    }
+
+    /// Collects the rules that can be called by the element before it
+    /// has matched any words.
+    fn left_calls(&self, element: &'a Element, called: &mut Vec<&'a str>) {
+        match *element {
+            Element::Sequence { ref children } => {
+                for c in children.iter() {
+                    self.left_calls(c, called);
+                    if !self.is_nullable(c) {
+                        break;
+                    }
+                }
+            }
+            Element::Alternative { ref children } | Element::Permutation { ref children, .. } => {
+                for c in children.iter() {
+                    self.left_calls(c, called);
+                }
+            }
+            Element::Repetition { ref child }
+            | Element::Optional { ref child }
+            | Element::Capture { ref child, .. } => self.left_calls(child, called),
+            Element::SeparatedList {
+                ref child,
+                ref separator,
+                ref last_separator,
+            } => {
+                self.left_calls(child, called);
+                if self.is_nullable(child) {
+                    self.left_calls(separator, called);
+                    if let Some(ref last) = *last_separator {
+                        self.left_calls(last, called);
+                    }
+                }
+            }
+            Element::RuleRef { ref name } => called.push(name),
+            Element::Word { .. }
+            | Element::List { .. }
+            | Element::Import { .. }
+            | Element::Dictation { .. }
+            | Element::DictationWord { .. }
+            | Element::SpellingLetter => {}
+        }
+    }
+}
@@ -0,0 +1,45 @@
+// the spoken forms Dragon uses for letters when spelling
+const NATO_ALPHABET: [&str; 26] = [
+    "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliett",
+    "kilo", "lima", "mike", "november", "oscar", "papa", "quebec", "romeo", "sierra", "tango",
+    "uniform", "victor", "whiskey", "x-ray", "yankee", "zulu",
+];
+
+/// Decodes a word recognized by Dragon's `dgnletters` rule, which is
+/// either a letter on its own, the spoken form of one (`alpha`) or both
+/// in Dragon's `written\spoken` form (`a\alpha`). The letter has the
+/// case Dragon wrote it in, which for a spoken form is the case of its
+/// first letter (`Alpha` is `A`), so callers that do not care about case
+/// should fold it themselves. Returns `None` for words that are not
+/// letters.
+pub fn decode_letter(word: &str) -> Option<char> {
+    let written = match word.find('\\') {
+        Some(index) => &word[..index],
+        None => word,
+    };
+
+    let mut chars = written.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_alphabetic() {
+            return Some(c);
+        }
+    }
+
+    if word.contains('\\') {
+        return None;
+    }
+
+    let lowercase = word.to_lowercase();
+    let spoken = match lowercase.as_str() {
+        "juliet" => "juliett",
+        "xray" => "x-ray",
+        other => other,
+    };
+
+    let uppercase = word.starts_with(char::is_uppercase);
+    NATO_ALPHABET
+        .iter()
+        .position(|&n| n == spoken)
+        .map(|i| (b'a' + i as u8) as char)
+        .map(|c| if uppercase { c.to_ascii_uppercase() } else { c })
+}
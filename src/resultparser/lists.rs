@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    pub text: String,
+    pub words: Vec<String>,
+}
+
+pub type Lists = HashMap<String, Vec<ListEntry>>;
+
+/// The contents of the lists of a grammar, shared between the grammar's
+/// `CommandGrammarControl` and the matchers for its results. Entries of
+/// more than one word match those words in sequence. A list that has
+/// never been filled or cleared matches any single word, like lists did
+/// before their contents were known.
+#[derive(Debug, Clone, Default)]
+pub struct ListContents {
+    lists: Arc<RwLock<Lists>>,
+}
+
+impl ListContents {
+    pub fn new() -> Self {
+        ListContents::default()
+    }
+
+    pub fn append(&self, list: &str, entry: &str) {
+        let entry = ListEntry {
+            text: entry.to_owned(),
+            words: entry.split_whitespace().map(|w| w.to_owned()).collect(),
+        };
+
+        let mut lists = self.lists.write().unwrap();
+        lists
+            .entry(list.to_owned())
+            .or_insert_with(Vec::new)
+            .push(entry);
+    }
+
+    /// Removes all entries with the given text.
+    pub fn remove(&self, list: &str, entry: &str) {
+        let mut lists = self.lists.write().unwrap();
+        if let Some(entries) = lists.get_mut(list) {
+            entries.retain(|e| e.text != entry);
+        }
+    }
+
+    /// Removes all entries, after which the list matches nothing.
+    pub fn clear(&self, list: &str) {
+        let mut lists = self.lists.write().unwrap();
+        lists.insert(list.to_owned(), Vec::new());
+    }
+
+    pub fn entries(&self, list: &str) -> Vec<String> {
+        let lists = self.lists.read().unwrap();
+        lists
+            .get(list)
+            .map(|entries| entries.iter().map(|e| e.text.clone()).collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    // matching holds on to the lists, so they can not change halfway
+    // through a match
+    pub fn read(&self) -> RwLockReadGuard<Lists> {
+        self.lists.read().unwrap()
+    }
+}
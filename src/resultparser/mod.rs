@@ -1,24 +1,296 @@
+mod budget;
 mod captures;
 mod compiler;
+mod de;
+mod fuzzy;
 mod instructions;
+mod lattice;
+mod leftrec;
+mod letters;
+mod lists;
+mod parses;
+mod pikevm;
+mod prediction;
+mod schema;
 mod vm;
 
-pub use self::captures::{CaptureTree, Match};
-use crate::engine::WordInfo;
+use self::budget::Budget;
+pub use self::budget::MatchBudget;
+pub use self::captures::{CaptureTree, ListMatch, Match, SpelledLetter};
+pub use self::compiler::Placeholder;
+pub use self::de::{from_match, DeError, MatchDeserializer};
+pub use self::fuzzy::{Edit, EditCosts, FuzzyMatch, UniformCosts};
+pub use self::instructions::Choice;
+pub use self::lattice::{ChoiceMatch, Lattice, LatticeMatch, LatticeNode};
+pub use self::letters::decode_letter;
+pub use self::lists::ListContents;
+pub use self::parses::{
+    rank_parses, FewestDictationWords, HighestWeight, MostLiteralWords, Parse, RankingPolicy,
+};
+pub use self::prediction::{Continuation, Prediction, PrefixStatus};
+pub use self::schema::{
+    grammar_json_schema, infer_schema, CaptureSchema, Cardinality, RuleSchema, SchemaError,
+};
+use crate::engine::{WordInfo, Words};
 use crate::grammar::Grammar;
+use crate::grammarcompiler::inline::{inline_rules, InlineOptions};
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
+#[derive(Fail, Debug)]
+pub enum MatchError {
+    #[fail(display = "words do not match the grammar")]
+    NoMatch,
+    #[fail(display = "matching exceeded its budget after {} steps", steps)]
+    BudgetExceeded { steps: u64 },
+    #[fail(display = "invalid matcher program: {}", _0)]
+    InvalidProgram(String),
+}
+
+#[derive(Fail, Debug)]
+pub enum MatcherError {
+    #[fail(display = "unknown rule name in grammar definition: {}", name)]
+    UnknownRule { name: String },
+    #[fail(display = "duplicate rule name in grammar definition: {}", name)]
+    DuplicateRule { name: String },
+    #[fail(
+        display = "permutation with {} children in grammar definition (at most {} allowed)",
+        count, max
+    )]
+    PermutationTooLarge { count: usize, max: usize },
+    #[fail(display = "permutation without children in grammar definition")]
+    EmptyPermutation,
+    #[fail(display = "left-recursive rule in grammar definition: {}", _0)]
+    LeftRecursion(String),
+    #[fail(
+        display = "dictation with at least {} and at most {} words in grammar definition",
+        min, max
+    )]
+    InvalidWordLimits { min: usize, max: usize },
+    #[fail(
+        display = "dictation word limit of {} in grammar definition (at most {} allowed)",
+        limit, max
+    )]
+    WordLimitTooLarge { limit: usize, max: usize },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MatcherOptions {
+    /// Imported rules are matched by the given placeholders. Imported
+    /// rules without a placeholder match one or more words, except
+    /// for `dgnwords` and `dgnletters` which match a single word.
+    pub placeholders: HashMap<String, Placeholder>,
+    /// Allow left-recursive rules. Each left-recursive call is only made
+    /// if the rule is not already being called at the same position more
+    /// often than there are words left, since every level of recursion
+    /// beyond that cannot match any words. Otherwise left recursion is
+    /// an error.
+    pub left_recursion: bool,
+    /// The contents of the lists, usually those of the grammar's
+    /// `CommandGrammarControl`. A list that has never been filled
+    /// matches any single word.
+    pub lists: ListContents,
+    /// Inline rules before compiling the matcher, usually with the same
+    /// options as the grammar that is loaded into Dragon.
+    pub inline: Option<InlineOptions>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Matcher {
     instructions: Vec<instructions::Instruction>,
+    // in the order of the split the program starts with
+    exported_rules: Vec<String>,
+    #[serde(skip)]
+    lists: ListContents,
 }
 
 impl Matcher {
+    /// Panics if the grammar is invalid.
+    #[deprecated(note = "panics on invalid grammars, use `try_new` instead")]
     pub fn new(grammar: &Grammar) -> Self {
-        Matcher {
-            instructions: compiler::compile_matcher(grammar),
-        }
+        Matcher::try_new(grammar).expect("invalid grammar")
+    }
+
+    pub fn try_new(grammar: &Grammar) -> Result<Self, MatcherError> {
+        Matcher::try_with_options(grammar, &MatcherOptions::default())
+    }
+
+    /// See `MatcherOptions::placeholders`.
+    pub fn try_with_placeholders(
+        grammar: &Grammar,
+        placeholders: &HashMap<String, Placeholder>,
+    ) -> Result<Self, MatcherError> {
+        let options = MatcherOptions {
+            placeholders: placeholders.clone(),
+            ..MatcherOptions::default()
+        };
+        Matcher::try_with_options(grammar, &options)
+    }
+
+    pub fn try_with_options(
+        grammar: &Grammar,
+        options: &MatcherOptions,
+    ) -> Result<Self, MatcherError> {
+        let inlined;
+        let grammar = match options.inline {
+            Some(ref inline) => {
+                inlined = inline_rules(grammar, inline);
+                &inlined
+            }
+            None => grammar,
+        };
+
+        Ok(Matcher {
+            instructions: compiler::compile_matcher(grammar, options)?,
+            exported_rules: grammar
+                .rules
+                .iter()
+                .filter(|r| r.exported)
+                .map(|r| r.name.clone())
+                .collect(),
+            lists: options.lists.clone(),
+        })
+    }
+
+    pub fn lists(&self) -> &ListContents {
+        &self.lists
+    }
+
+    /// Replaces the contents of the lists, for instance for a matcher
+    /// that was retrieved from a `GrammarCache`.
+    pub fn set_lists(&mut self, lists: ListContents) {
+        self.lists = lists;
+    }
+
+    /// Matches with the default `MatchBudget`, so an utterance that takes
+    /// too many steps fails with `MatchError::BudgetExceeded` rather than
+    /// matching eventually. Use `perform_match_with_budget` with
+    /// `MatchBudget::unlimited()` to match without a limit.
+    pub fn perform_match<'a>(&'a self, string: &[WordInfo]) -> Result<Vec<Match<'a>>, MatchError> {
+        self.perform_match_with_budget(string, &MatchBudget::default())
+    }
+
+    pub fn perform_match_with_budget<'a>(
+        &'a self,
+        string: &[WordInfo],
+        budget: &MatchBudget,
+    ) -> Result<Vec<Match<'a>>, MatchError> {
+        let mut budget = Budget::new(budget);
+        pikevm::perform_match(&self.instructions, &self.lists.read(), string, &mut budget)
+    }
+
+    /// Same as `perform_match_with_budget`, but using a backtracking
+    /// interpreter, which can take exponential time on grammars with many
+    /// nested alternatives or repetitions.
+    pub fn perform_match_backtracking<'a>(
+        &'a self,
+        string: &[WordInfo],
+        budget: &MatchBudget,
+    ) -> Result<Vec<Match<'a>>, MatchError> {
+        vm::perform_match(&self.instructions, &self.lists.read(), string, budget)
+    }
+
+    /// Finds up to `limit` distinct parses of an ambiguous utterance, in
+    /// the order they are found by the backtracking interpreter. The
+    /// first one is the match returned by `perform_match`. Use
+    /// `rank_parses` to choose between them.
+    pub fn perform_match_all<'a>(
+        &'a self,
+        string: &[WordInfo],
+        limit: usize,
+        budget: &MatchBudget,
+    ) -> Result<Vec<Parse<'a>>, MatchError> {
+        vm::perform_match_all(
+            &self.instructions,
+            &self.lists.read(),
+            string,
+            budget,
+            limit,
+        )
+    }
+
+    /// Finds the words that can follow a prefix of an utterance, and
+    /// whether the prefix matches on its own. Only the given exported
+    /// rules are considered, or all of them if `active_rules` is `None`.
+    /// Names of rules that are not exported are ignored.
+    pub fn predict<'a>(
+        &'a self,
+        prefix: &[WordInfo],
+        active_rules: Option<&[&str]>,
+        budget: &MatchBudget,
+    ) -> Result<Prediction<'a>, MatchError> {
+        let starts = match active_rules {
+            Some(active) => self.rule_starts(active)?,
+            None => vec![0],
+        };
+
+        pikevm::predict(
+            &self.instructions,
+            &self.lists.read(),
+            prefix,
+            &starts,
+            budget,
+        )
     }
 
-    pub fn perform_match<'a>(&'a self, string: &[WordInfo]) -> Option<Vec<Match<'a>>> {
-        vm::perform_match(&self.instructions, string)
+    /// Finds the parse that needs the edits with the lowest total cost
+    /// to match the words, which is the same as the match returned by
+    /// `perform_match` if there is one. Since every utterance can be
+    /// matched by editing it enough, this only fails if it exceeds its
+    /// budget.
+    pub fn perform_fuzzy_match<'a>(
+        &'a self,
+        string: &[WordInfo],
+        costs: &dyn EditCosts,
+        budget: &MatchBudget,
+    ) -> Result<FuzzyMatch<'a>, MatchError> {
+        fuzzy::perform_fuzzy_match(
+            &self.instructions,
+            &self.lists.read(),
+            string,
+            costs,
+            budget,
+        )
+    }
+
+    /// Matches the alternative recognitions of an utterance, which are
+    /// ordered from best to worst, and returns the match of the best one
+    /// that matches along with its index. The budget is shared by all of
+    /// them.
+    pub fn perform_match_choices<'a>(
+        &'a self,
+        choices: &[Words],
+        budget: &MatchBudget,
+    ) -> Result<ChoiceMatch<'a>, MatchError> {
+        lattice::perform_match_choices(&self.instructions, &self.lists.read(), choices, budget)
+    }
+
+    /// Matches the paths through a lattice of alternative words from the
+    /// highest total score to the lowest, and returns the first one that
+    /// matches. The budget is shared by all of them.
+    pub fn perform_match_lattice<'a>(
+        &'a self,
+        lattice: &Lattice,
+        budget: &MatchBudget,
+    ) -> Result<LatticeMatch<'a>, MatchError> {
+        lattice::perform_match_lattice(&self.instructions, &self.lists.read(), lattice, budget)
+    }
+
+    // the program starts with a split into the exported rules
+    fn rule_starts(&self, rules: &[&str]) -> Result<Vec<usize>, MatchError> {
+        let targets = match self.instructions.first() {
+            Some(&instructions::Instruction::Split(ref targets)) => targets,
+            _ => return vm::invalid("program does not start with a split"),
+        };
+
+        let mut starts = Vec::new();
+        for (name, target) in self.exported_rules.iter().zip(targets.iter()) {
+            if rules.contains(&name.as_str()) {
+                starts.push(vm::address(target)?);
+            }
+        }
+
+        Ok(starts)
     }
 }
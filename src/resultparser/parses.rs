@@ -0,0 +1,93 @@
+//! All parses of an ambiguous utterance, and policies for choosing
+//! between them.
+
+use super::captures::Match;
+use super::instructions::Choice;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Parse<'a> {
+    pub captures: Vec<Match<'a>>,
+    /// The children taken in alternatives, in the order they were taken.
+    pub choices: Vec<&'a Choice>,
+    /// The number of words matched by words of the grammar.
+    pub literal_words: usize,
+    /// The number of words matched by dictation.
+    pub dictation_words: usize,
+}
+
+pub trait RankingPolicy {
+    /// Orders parses from best to worst.
+    fn compare(&self, a: &Parse, b: &Parse) -> Ordering;
+}
+
+impl<F> RankingPolicy for F
+where
+    F: Fn(&Parse, &Parse) -> Ordering,
+{
+    fn compare(&self, a: &Parse, b: &Parse) -> Ordering {
+        self(a, b)
+    }
+}
+
+/// Sorts parses from best to worst. Parses the policy considers equal
+/// keep the order the matcher found them in.
+pub fn rank_parses<P: RankingPolicy + ?Sized>(parses: &mut [Parse], policy: &P) {
+    parses.sort_by(|a, b| policy.compare(a, b));
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FewestDictationWords;
+
+impl RankingPolicy for FewestDictationWords {
+    fn compare(&self, a: &Parse, b: &Parse) -> Ordering {
+        a.dictation_words.cmp(&b.dictation_words)
+    }
+}
+
+/// Prefers the parses that match the most words literally instead of
+/// through dictation, lists or imported rules.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MostLiteralWords;
+
+impl RankingPolicy for MostLiteralWords {
+    fn compare(&self, a: &Parse, b: &Parse) -> Ordering {
+        b.literal_words.cmp(&a.literal_words)
+    }
+}
+
+/// Prefers the parses with the highest total weight of the choices they
+/// made. Choices without a weight have a weight of zero, and a total
+/// weight of NaN ranks like negative infinity.
+#[derive(Debug, Clone, Default)]
+pub struct HighestWeight {
+    pub weights: HashMap<Choice, f64>,
+}
+
+impl HighestWeight {
+    pub fn weight(&self, parse: &Parse) -> f64 {
+        parse
+            .choices
+            .iter()
+            .map(|&c| self.weights.get(c).cloned().unwrap_or(0.0))
+            .sum()
+    }
+}
+
+impl RankingPolicy for HighestWeight {
+    fn compare(&self, a: &Parse, b: &Parse) -> Ordering {
+        // sorting needs a total order, which NaN would break
+        let total = |parse: &Parse| {
+            let weight = self.weight(parse);
+            if weight.is_nan() {
+                f64::NEG_INFINITY
+            } else {
+                weight
+            }
+        };
+
+        total(b).total_cmp(&total(a))
+    }
+}
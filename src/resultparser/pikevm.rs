@@ -0,0 +1,542 @@
+//! A matcher that runs all threads in lockstep over the words, in the
+//! style of Pike's regular expression VM.
+//!
+//! Threads are kept in priority order and a thread is dropped if a thread
+//! of higher priority has already reached the same state at the same
+//! position, so the result is the same as the first match found by the
+//! backtracking VM. Since the number of distinct states only depends on
+//! the grammar, matching takes time linear in the number of words for a
+//! given grammar, no matter how many alternatives and repetitions it has.
+//! The only exceptions are recursive rules, where the call stack can
+//! grow with the number of words, and guarded calls of left-recursive
+//! rules, which are tracked along with the position they were made at.
+
+use super::budget::{Budget, MatchBudget};
+use super::captures::{CaptureBuilder, Match};
+use super::instructions::Instruction;
+use super::letters::decode_letter;
+use super::lists::Lists;
+use super::prediction::{Continuation, Prediction, PrefixStatus};
+use super::vm::{address, fetch, guard, invalid, permutation_bit, Result};
+use super::MatchError;
+use crate::engine::WordInfo;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+pub fn perform_match<'a>(
+    program: &'a [Instruction],
+    lists: &Lists,
+    string: &[WordInfo],
+    budget: &mut Budget,
+) -> Result<Vec<Match<'a>>> {
+    let threads = run(program, lists, string, &[0], budget)?;
+
+    match threads.matched {
+        Some(log) => replay_captures(&log, lists),
+        None => Err(MatchError::NoMatch),
+    }
+}
+
+/// Matches starting at each of the addresses in `starts` instead of at
+/// the start of the program, which is how matching can be limited to
+/// some of the exported rules.
+pub fn predict<'a>(
+    program: &'a [Instruction],
+    lists: &Lists,
+    prefix: &[WordInfo],
+    starts: &[usize],
+    budget: &MatchBudget,
+) -> Result<Prediction<'a>> {
+    let mut budget = Budget::new(budget);
+    let threads = run(program, lists, prefix, starts, &mut budget)?;
+
+    let mut continuations = Vec::new();
+    for t in threads.threads.iter() {
+        // a list without entries can not take the next word
+        if let Instruction::List(ref name) = *fetch(program, t.state.program_pointer)? {
+            let entries = lists.get(name).map(|e| &e[..]);
+            if entries.map_or(false, |e| e.iter().all(|e| e.words.is_empty())) {
+                continue;
+            }
+        }
+
+        let c = continuation(program, lists, &t.state)?;
+        if !continuations.contains(&c) {
+            continuations.push(c);
+        }
+    }
+
+    let status = if threads.matched.is_some() {
+        PrefixStatus::Complete
+    } else if !continuations.is_empty() {
+        PrefixStatus::Viable
+    } else {
+        PrefixStatus::Dead
+    };
+
+    Ok(Prediction {
+        status: status,
+        continuations: continuations,
+    })
+}
+
+// what a thread waiting for the next word accepts
+pub fn continuation<'a>(
+    program: &'a [Instruction],
+    lists: &Lists,
+    state: &State,
+) -> Result<Continuation<'a>> {
+    let pc = state.program_pointer;
+
+    let c = match *fetch(program, pc)? {
+        Instruction::Literal(ref word) => Continuation::Word(word.clone()),
+        Instruction::SpellingLetter => Continuation::SpellingLetter,
+        Instruction::List(ref name) => match state.list_entry {
+            Some((index, matched)) => {
+                let entry = lists.get(name).and_then(|e| e.get(index));
+                match entry.and_then(|e| e.words.get(matched)) {
+                    Some(word) => Continuation::Word(word.clone()),
+                    None => return invalid("list entry out of range"),
+                }
+            }
+            None if !lists.contains_key(name) => Continuation::AnyWord,
+            None => Continuation::List(name),
+        },
+        Instruction::AnyWord { dictation: true } | Instruction::AnyWordExcept(_) => {
+            Continuation::Dictation
+        }
+        Instruction::AnyWord { dictation: false } => Continuation::AnyWord,
+        _ => return invalid("thread waiting for a word at an instruction that matches none"),
+    };
+
+    Ok(c)
+}
+
+/// Runs all threads over the words. Stops early, with no threads left,
+/// as soon as the words can no longer match.
+fn run<'a>(
+    program: &'a [Instruction],
+    lists: &Lists,
+    string: &[WordInfo],
+    starts: &[usize],
+    budget: &mut Budget,
+) -> Result<ThreadList<'a>> {
+    let mut current = ThreadList::new(program, 0, string.len());
+    for &start in starts.iter() {
+        current.add(Thread::new(start), budget)?;
+    }
+
+    for (position, word) in string.iter().enumerate() {
+        let mut next = ThreadList::new(program, position + 1, string.len());
+
+        for mut t in current.threads.into_iter() {
+            budget.step()?;
+
+            let instruction = fetch(program, t.state.program_pointer)?;
+            let entries = match *instruction {
+                Instruction::List(ref name) => lists.get(name),
+                _ => None,
+            };
+
+            if let Some(entries) = entries {
+                // a thread that is halfway through an entry can only
+                // continue with that entry
+                let (candidates, matched) = match t.state.list_entry {
+                    Some((index, matched)) => (index..index + 1, matched),
+                    None => (0..entries.len(), 0),
+                };
+
+                for index in candidates {
+                    budget.step()?;
+
+                    if entries[index].words.get(matched) != Some(&word.text) {
+                        continue;
+                    }
+
+                    let mut advanced = t.clone();
+                    if advanced.state.list_entry.is_none() {
+                        advanced.choose_entry(index, position);
+                    }
+                    advanced.state.progress.clear();
+                    advance(program, lists, &mut advanced, position)?;
+                    next.add(advanced, budget)?;
+                }
+            } else if matches_word(instruction, lists, &word.text) {
+                if let Instruction::SpellingLetter = *instruction {
+                    if t.state.suppressed == 0 {
+                        let letter = spelled_letter(&word.text)?;
+                        t.log(CaptureEvent::Letter(position, letter));
+                    }
+                }
+
+                t.state.progress.clear();
+                advance(program, lists, &mut t, position)?;
+                next.add(t, budget)?;
+            }
+        }
+
+        if next.threads.is_empty() && next.matched.is_none() {
+            return Ok(next);
+        }
+
+        current = next;
+    }
+
+    Ok(current)
+}
+
+/// Moves a thread past the word it is waiting for, which is at
+/// `position`. A thread at a list must have chosen its entry.
+pub fn advance<'a>(
+    program: &'a [Instruction],
+    lists: &Lists,
+    t: &mut Thread<'a>,
+    position: usize,
+) -> Result<()> {
+    if let Instruction::List(ref name) = *fetch(program, t.state.program_pointer)? {
+        let (index, matched) = match t.state.list_entry {
+            Some(entry) => entry,
+            None if !lists.contains_key(name) => {
+                t.state.program_pointer += 1;
+                return Ok(());
+            }
+            None => return invalid("list entry has not been chosen"),
+        };
+        let length = match lists.get(name).and_then(|e| e.get(index)) {
+            Some(entry) => entry.words.len(),
+            None => return invalid("list entry out of range"),
+        };
+
+        if matched + 1 < length {
+            t.state.list_entry = Some((index, matched + 1));
+            return Ok(());
+        }
+
+        t.state.list_entry = None;
+        if t.state.suppressed == 0 {
+            let start = t.list_start;
+            t.log(CaptureEvent::ListEntry(name, index, start, position + 1));
+        }
+    }
+
+    t.state.program_pointer += 1;
+    Ok(())
+}
+
+// the letter of a word that a `SpellingLetter` instruction has matched
+pub fn spelled_letter(word: &str) -> Result<char> {
+    match decode_letter(word) {
+        Some(letter) => Ok(letter),
+        None => invalid("spelling letter matched a word that is not a letter"),
+    }
+}
+
+pub fn matches_word(instruction: &Instruction, lists: &Lists, word: &str) -> bool {
+    match *instruction {
+        Instruction::Literal(ref grammar_word) => grammar_word == word,
+        Instruction::AnyWord { .. } => true,
+        Instruction::AnyWordExcept(ref excluded) => !excluded.contains(word),
+        Instruction::SpellingLetter => decode_letter(word).is_some(),
+        // a list that has never been filled matches any word
+        Instruction::List(ref name) => !lists.contains_key(name),
+        _ => false,
+    }
+}
+
+/// Everything that determines how a thread continues, which excludes
+/// its captures.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct State {
+    pub program_pointer: usize,
+    call_stack: Vec<usize>,
+    permutations: Vec<u64>,
+    counters: Vec<usize>,
+    pub suppressed: usize,
+    /// The `Progress` instructions that were passed since the last word,
+    /// sorted by address. These are the ones that would see no progress
+    /// when passed again in the backtracking VM.
+    pub progress: Vec<usize>,
+    /// The guarded calls that have not returned yet, along with the
+    /// positions at which they were made.
+    guards: Vec<(usize, usize)>,
+    /// The index of the list entry being matched and the number of its
+    /// words matched so far, for entries of more than one word.
+    pub list_entry: Option<(usize, usize)>,
+}
+
+#[derive(Debug)]
+pub enum CaptureEvent<'a> {
+    Start(&'a str, usize),
+    Stop(usize),
+    ListEntry(&'a str, usize, usize, usize),
+    Letter(usize, char),
+}
+
+// threads share the captures they have in common, so splitting a thread
+// does not copy its captures
+#[derive(Debug)]
+pub struct CaptureLog<'a> {
+    event: CaptureEvent<'a>,
+    previous: Option<Rc<CaptureLog<'a>>>,
+}
+
+pub fn replay_captures<'a>(
+    log: &Option<Rc<CaptureLog<'a>>>,
+    lists: &Lists,
+) -> Result<Vec<Match<'a>>> {
+    let mut events = Vec::new();
+    let mut current = log.as_ref();
+    while let Some(entry) = current {
+        events.push(&entry.event);
+        current = entry.previous.as_ref();
+    }
+
+    let mut builder = CaptureBuilder::new();
+    for e in events.into_iter().rev() {
+        match *e {
+            CaptureEvent::Start(name, position) => builder.capture_start(name, position),
+            CaptureEvent::Stop(position) => builder.capture_stop(position)?,
+            CaptureEvent::ListEntry(list, index, start, end) => {
+                let entry = match lists.get(list).and_then(|e| e.get(index)) {
+                    Some(entry) => entry,
+                    None => return invalid("list entry out of range"),
+                };
+                builder.list_entry(list, &entry.text, (start, end));
+            }
+            CaptureEvent::Letter(position, letter) => builder.letter(position, letter),
+        }
+    }
+
+    builder.done()
+}
+
+#[derive(Debug, Clone)]
+pub struct Thread<'a> {
+    pub state: State,
+    pub captures: Option<Rc<CaptureLog<'a>>>,
+    /// Where the list entry being matched started.
+    list_start: usize,
+}
+
+impl<'a> Thread<'a> {
+    pub fn new(program_pointer: usize) -> Self {
+        Thread {
+            state: State {
+                program_pointer: program_pointer,
+                call_stack: Vec::new(),
+                permutations: Vec::new(),
+                counters: Vec::new(),
+                suppressed: 0,
+                progress: Vec::new(),
+                guards: Vec::new(),
+                list_entry: None,
+            },
+            captures: None,
+            list_start: 0,
+        }
+    }
+
+    pub fn choose_entry(&mut self, index: usize, position: usize) {
+        self.state.list_entry = Some((index, 0));
+        self.list_start = position;
+    }
+
+    pub fn log(&mut self, event: CaptureEvent<'a>) {
+        let previous = self.captures.take();
+        self.captures = Some(Rc::new(CaptureLog {
+            event: event,
+            previous: previous,
+        }));
+    }
+}
+
+pub struct ThreadList<'a> {
+    program: &'a [Instruction],
+    position: usize,
+    length: usize,
+    /// Threads waiting for the next word, in priority order.
+    pub threads: Vec<Thread<'a>>,
+    visited: HashSet<State>,
+    /// The captures of the first thread that matched all words.
+    pub matched: Option<Option<Rc<CaptureLog<'a>>>>,
+    /// The captures of the first thread that matched the exported rule
+    /// before all words were matched.
+    pub returned: Option<Option<Rc<CaptureLog<'a>>>>,
+}
+
+impl<'a> ThreadList<'a> {
+    pub fn new(program: &'a [Instruction], position: usize, length: usize) -> Self {
+        ThreadList {
+            program: program,
+            position: position,
+            length: length,
+            threads: Vec::new(),
+            visited: HashSet::new(),
+            matched: None,
+            returned: None,
+        }
+    }
+
+    /// Follows all instructions that do not consume a word, in the same
+    /// order as the backtracking VM.
+    pub fn add(&mut self, thread: Thread<'a>, budget: &mut Budget) -> Result<()> {
+        let mut pending = vec![thread];
+
+        while let Some(mut t) = pending.pop() {
+            loop {
+                budget.step()?;
+
+                if !self.visited.insert(t.state.clone()) {
+                    break;
+                }
+
+                let next = fetch(self.program, t.state.program_pointer)?;
+                t.state.program_pointer += 1;
+
+                match *next {
+                    Instruction::Literal(_)
+                    | Instruction::AnyWord { .. }
+                    | Instruction::AnyWordExcept(_)
+                    | Instruction::SpellingLetter
+                    | Instruction::List(_) => {
+                        t.state.program_pointer -= 1;
+                        self.threads.push(t);
+                        break;
+                    }
+                    Instruction::CaptureStart(ref name) => {
+                        if t.state.suppressed == 0 {
+                            t.log(CaptureEvent::Start(name, self.position));
+                        }
+                    }
+                    Instruction::CaptureStop => {
+                        if t.state.suppressed == 0 {
+                            t.log(CaptureEvent::Stop(self.position));
+                        }
+                    }
+                    Instruction::SuppressStart => {
+                        t.state.suppressed += 1;
+                    }
+                    Instruction::SuppressStop => {
+                        if t.state.suppressed == 0 {
+                            return invalid("suppression stopped without being started");
+                        }
+                        t.state.suppressed -= 1;
+                    }
+                    Instruction::Return => {
+                        if let Some(return_address) = t.state.call_stack.pop() {
+                            t.state.program_pointer = return_address;
+                        } else {
+                            if self.position == self.length {
+                                if self.matched.is_none() {
+                                    self.matched = Some(t.captures);
+                                }
+                            } else if self.returned.is_none() {
+                                self.returned = Some(t.captures);
+                            }
+                            break;
+                        }
+                    }
+                    Instruction::RuleCall(ref target) => {
+                        t.state.call_stack.push(t.state.program_pointer);
+                        t.state.program_pointer = address(target)?;
+                    }
+                    Instruction::GuardedCall(ref target) => {
+                        let target = address(target)?;
+                        let remaining = self.length - self.position;
+
+                        if !guard(&mut t.state.guards, target, self.position, remaining) {
+                            break;
+                        }
+
+                        t.state.call_stack.push(t.state.program_pointer);
+                        t.state.program_pointer = target;
+                    }
+                    Instruction::GuardRelease => {
+                        if t.state.guards.pop().is_none() {
+                            return invalid("guard released without being taken");
+                        }
+                    }
+                    Instruction::Jump(ref target) => {
+                        t.state.program_pointer = address(target)?;
+                    }
+                    Instruction::Split(ref targets) => {
+                        let (first, rest) = match targets.split_first() {
+                            Some(split) => split,
+                            None => break,
+                        };
+
+                        for target in rest.iter().rev() {
+                            let mut branch = t.clone();
+                            branch.state.program_pointer = address(target)?;
+                            pending.push(branch);
+                        }
+
+                        t.state.program_pointer = address(first)?;
+                    }
+                    Instruction::PermutationStart => {
+                        t.state.permutations.push(0);
+                    }
+                    Instruction::PermutationClaim(child) => {
+                        let bit = permutation_bit(child)?;
+                        let claimed = match t.state.permutations.last_mut() {
+                            Some(claimed) => claimed,
+                            None => return invalid("permutation claimed outside of permutation"),
+                        };
+
+                        if *claimed & bit != 0 {
+                            break;
+                        }
+
+                        *claimed |= bit;
+                    }
+                    Instruction::PermutationEnd {
+                        all_required,
+                        count,
+                    } => {
+                        let claimed = match t.state.permutations.pop() {
+                            Some(claimed) => claimed,
+                            None => return invalid("permutation ended without being started"),
+                        };
+                        let matched = claimed.count_ones() as usize;
+
+                        if matched == 0 || (all_required && matched != count) {
+                            break;
+                        }
+                    }
+                    Instruction::CounterStart => {
+                        t.state.counters.push(0);
+                    }
+                    Instruction::CounterBelow(limit) => match t.state.counters.last() {
+                        Some(&count) if count < limit => {}
+                        Some(_) => break,
+                        None => return invalid("counter checked without being started"),
+                    },
+                    Instruction::CounterIncrement => match t.state.counters.last_mut() {
+                        Some(count) => *count += 1,
+                        None => return invalid("counter incremented without being started"),
+                    },
+                    Instruction::CounterStop => {
+                        if t.state.counters.pop().is_none() {
+                            return invalid("counter stopped without being started");
+                        }
+                    }
+                    Instruction::Progress => {
+                        let pc = t.state.program_pointer;
+                        match t.state.progress.binary_search(&pc) {
+                            Ok(_) => break,
+                            Err(index) => t.state.progress.insert(index, pc),
+                        }
+                    }
+                    // only needed to tell parses apart
+                    Instruction::Choice(_)
+                    | Instruction::DictationStart
+                    | Instruction::DictationStop
+                    | Instruction::NoOp
+                    | Instruction::Label(_) => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefixStatus {
+    /// The prefix matches the grammar on its own. It may also be the
+    /// start of longer matches.
+    Complete,
+    /// The prefix does not match on its own, but is the start of at
+    /// least one match.
+    Viable,
+    /// No words can be added to make the prefix match.
+    Dead,
+}
+
+/// What can follow a prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Continuation<'a> {
+    Word(String),
+    /// Any entry of the named list.
+    List(&'a str),
+    Dictation,
+    SpellingLetter,
+    /// Any word, for an imported rule or a list that has never been
+    /// filled.
+    AnyWord,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Prediction<'a> {
+    pub status: PrefixStatus,
+    /// The possible next words, in the order the matcher would try them.
+    pub continuations: Vec<Continuation<'a>>,
+}
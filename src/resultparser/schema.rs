@@ -0,0 +1,351 @@
+use crate::grammar::{Element, Grammar, Rule};
+use failure::Fail;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::mem;
+
+#[derive(Fail, Debug)]
+pub enum SchemaError {
+    #[fail(display = "unknown rule name in grammar definition: {}", name)]
+    UnknownRule { name: String },
+    #[fail(display = "cannot infer capture schema of recursive rule: {}", name)]
+    RecursiveRule { name: String },
+}
+
+pub type Result<T> = ::std::result::Result<T, SchemaError>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cardinality {
+    One,
+    Optional,
+    OneOrMore,
+    ZeroOrMore,
+}
+
+impl Cardinality {
+    fn from_bounds(required: bool, repeated: bool) -> Self {
+        match (required, repeated) {
+            (true, false) => Cardinality::One,
+            (false, false) => Cardinality::Optional,
+            (true, true) => Cardinality::OneOrMore,
+            (false, true) => Cardinality::ZeroOrMore,
+        }
+    }
+
+    pub fn is_required(self) -> bool {
+        self == Cardinality::One || self == Cardinality::OneOrMore
+    }
+
+    pub fn is_repeated(self) -> bool {
+        self == Cardinality::OneOrMore || self == Cardinality::ZeroOrMore
+    }
+
+    // both occur one after the other
+    fn then(self, other: Cardinality) -> Self {
+        Cardinality::from_bounds(self.is_required() || other.is_required(), true)
+    }
+
+    // either one or the other occurs
+    fn or(self, other: Cardinality) -> Self {
+        Cardinality::from_bounds(
+            self.is_required() && other.is_required(),
+            self.is_repeated() || other.is_repeated(),
+        )
+    }
+
+    fn optional(self) -> Self {
+        Cardinality::from_bounds(false, self.is_repeated())
+    }
+
+    fn repeated(self) -> Self {
+        Cardinality::from_bounds(self.is_required(), true)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureSchema {
+    pub name: String,
+    pub cardinality: Cardinality,
+    pub children: Vec<CaptureSchema>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleSchema {
+    pub name: String,
+    pub captures: Vec<CaptureSchema>,
+}
+
+pub fn infer_schema(grammar: &Grammar) -> Result<Vec<RuleSchema>> {
+    let inference = Inference::new(grammar);
+
+    grammar
+        .rules
+        .iter()
+        .filter(|r| r.exported)
+        .map(|r| {
+            Ok(RuleSchema {
+                name: r.name.clone(),
+                captures: inference.infer_rule(r)?,
+            })
+        })
+        .collect()
+}
+
+pub fn grammar_json_schema(schemas: &[RuleSchema]) -> Value {
+    let mut definitions = Map::new();
+    let mut alternatives = Vec::new();
+
+    for s in schemas.iter() {
+        definitions.insert(s.name.clone(), captures_json_schema(&s.captures));
+        alternatives.push(json!({ "$ref": format!("#/$defs/{}", s.name) }));
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2019-09/schema",
+        "$defs": definitions,
+        "anyOf": alternatives,
+    })
+}
+
+impl RuleSchema {
+    pub fn to_json_schema(&self) -> Value {
+        let mut schema = captures_json_schema(&self.captures);
+        if let Value::Object(ref mut map) = schema {
+            map.insert(
+                "$schema".to_owned(),
+                json!("https://json-schema.org/draft/2019-09/schema"),
+            );
+            map.insert("title".to_owned(), json!(self.name));
+        }
+        schema
+    }
+}
+
+fn captures_json_schema(captures: &[CaptureSchema]) -> Value {
+    // anyOf and allOf must not be empty
+    if captures.is_empty() {
+        return json!({ "type": "array", "maxItems": 0 });
+    }
+
+    let nodes = captures
+        .iter()
+        .map(|c| node_json_schema(c))
+        .collect::<Vec<_>>();
+
+    let counts = captures
+        .iter()
+        .map(|c| {
+            let mut count = json!({
+                "contains": { "properties": { "name": { "const": c.name } } },
+                "minContains": if c.cardinality.is_required() { 1 } else { 0 },
+            });
+            if !c.cardinality.is_repeated() {
+                count["maxContains"] = json!(1);
+            }
+            count
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "type": "array",
+        "items": { "anyOf": nodes },
+        "allOf": counts,
+    })
+}
+
+fn node_json_schema(capture: &CaptureSchema) -> Value {
+    json!({
+        "type": "object",
+        "required": ["name", "slice", "children"],
+        "properties": {
+            "name": { "const": capture.name },
+            "slice": {
+                "type": "array",
+                "items": { "type": "integer", "minimum": 0 },
+                "minItems": 2,
+                "maxItems": 2,
+            },
+            "children": captures_json_schema(&capture.children),
+        },
+    })
+}
+
+// Sibling captures with the same name are merged into a single entry,
+// because a client can only tell them apart by their position in the
+// list of children. Each instance has the children of only one of the
+// merged captures, so their children are merged like alternatives.
+fn merge_into(target: &mut Vec<CaptureSchema>, capture: CaptureSchema) {
+    if let Some(existing) = target.iter_mut().find(|c| c.name == capture.name) {
+        existing.cardinality = existing.cardinality.then(capture.cardinality);
+        let children = mem::replace(&mut existing.children, Vec::new());
+        existing.children = alternative(children, capture.children);
+    } else {
+        target.push(capture);
+    }
+}
+
+fn sequence(left: Vec<CaptureSchema>, right: Vec<CaptureSchema>) -> Vec<CaptureSchema> {
+    let mut result = left;
+    for c in right {
+        merge_into(&mut result, c);
+    }
+    result
+}
+
+fn alternative(left: Vec<CaptureSchema>, right: Vec<CaptureSchema>) -> Vec<CaptureSchema> {
+    let mut result = Vec::new();
+
+    for mut c in left {
+        if !right.iter().any(|r| r.name == c.name) {
+            c.cardinality = c.cardinality.optional();
+        }
+        result.push(c);
+    }
+
+    for mut c in right {
+        if let Some(existing) = result.iter_mut().find(|e| e.name == c.name) {
+            existing.cardinality = existing.cardinality.or(c.cardinality);
+            // a child is only known to be present if it is present in
+            // both alternatives
+            let children = mem::replace(&mut existing.children, Vec::new());
+            existing.children = alternative(children, c.children);
+        } else {
+            c.cardinality = c.cardinality.optional();
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+fn map_cardinality<F>(captures: &mut [CaptureSchema], f: F)
+where
+    F: Fn(Cardinality) -> Cardinality + Copy,
+{
+    for c in captures.iter_mut() {
+        c.cardinality = f(c.cardinality);
+    }
+}
+
+struct Inference<'a> {
+    rules: HashMap<&'a str, &'a Rule>,
+}
+
+impl<'a> Inference<'a> {
+    fn new(grammar: &'a Grammar) -> Self {
+        let rules = grammar.rules.iter().map(|r| (&r.name as &str, r)).collect();
+
+        Inference { rules: rules }
+    }
+
+    fn infer_rule(&self, rule: &'a Rule) -> Result<Vec<CaptureSchema>> {
+        let mut active = vec![&rule.name as &str];
+        self.infer_element(&rule.definition, &mut active)
+    }
+
+    fn infer_element(
+        &self,
+        element: &'a Element,
+        active: &mut Vec<&'a str>,
+    ) -> Result<Vec<CaptureSchema>> {
+        let result = match *element {
+            Element::Sequence { ref children } => {
+                let mut result = Vec::new();
+                for c in children.iter() {
+                    let captures = self.infer_element(c, active)?;
+                    result = sequence(result, captures);
+                }
+                result
+            }
+            Element::Alternative { ref children } => {
+                let mut result: Option<Vec<CaptureSchema>> = None;
+                for c in children.iter() {
+                    let captures = self.infer_element(c, active)?;
+                    result = Some(match result {
+                        Some(previous) => alternative(previous, captures),
+                        None => captures,
+                    });
+                }
+                result.unwrap_or_else(Vec::new)
+            }
+            Element::Permutation {
+                ref children,
+                all_required,
+            } => {
+                let mut result = Vec::new();
+                for c in children.iter() {
+                    let mut captures = self.infer_element(c, active)?;
+                    if !all_required {
+                        map_cardinality(&mut captures, Cardinality::optional);
+                    }
+                    result = sequence(result, captures);
+                }
+                result
+            }
+            Element::Repetition { ref child } => {
+                let mut result = self.infer_element(child, active)?;
+                map_cardinality(&mut result, Cardinality::repeated);
+                result
+            }
+            Element::SeparatedList {
+                ref child,
+                ref separator,
+                ref last_separator,
+            } => {
+                // separators are still checked for unknown rules, even
+                // though their captures are dropped
+                self.infer_element(separator, active)?;
+                if let Some(ref last) = *last_separator {
+                    self.infer_element(last, active)?;
+                }
+
+                let mut result = self.infer_element(child, active)?;
+                map_cardinality(&mut result, Cardinality::repeated);
+                result
+            }
+            Element::Optional { ref child } => {
+                let mut result = self.infer_element(child, active)?;
+                map_cardinality(&mut result, Cardinality::optional);
+                result
+            }
+            Element::Capture {
+                ref name,
+                ref child,
+            } => vec![CaptureSchema {
+                name: name.clone(),
+                cardinality: Cardinality::One,
+                children: self.infer_element(child, active)?,
+            }],
+            Element::RuleRef { ref name } => {
+                let name: &'a str = name;
+                let rule = self
+                    .rules
+                    .get(name)
+                    .ok_or_else(|| SchemaError::UnknownRule {
+                        name: name.to_owned(),
+                    })?;
+
+                if active.contains(&name) {
+                    return Err(SchemaError::RecursiveRule {
+                        name: name.to_owned(),
+                    });
+                }
+
+                active.push(name);
+                let result = self.infer_element(&rule.definition, active)?;
+                active.pop();
+                result
+            }
+            Element::Word { .. }
+            | Element::List { .. }
+            | Element::Import { .. }
+            | Element::Dictation { .. }
+            | Element::DictationWord { .. }
+            | Element::SpellingLetter => Vec::new(),
+        };
+
+        Ok(result)
+    }
+}
@@ -1,113 +1,434 @@
+use super::budget::{Budget, MatchBudget};
 use super::captures::{CaptureBuilder, Match};
-use super::instructions::Instruction;
+use super::instructions::{Choice, Instruction, JumpTarget};
+use super::letters::decode_letter;
+use super::lists::{ListEntry, Lists};
+use super::parses::Parse;
+use super::MatchError;
 use crate::engine::WordInfo;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+pub type Result<T> = ::std::result::Result<T, MatchError>;
 
 pub fn perform_match<'a, 'c>(
     program: &'a [Instruction],
+    lists: &'c Lists,
     string: &'c [WordInfo],
-) -> Option<Vec<Match<'a>>> {
+    budget: &MatchBudget,
+) -> Result<Vec<Match<'a>>> {
+    let mut parses = perform_match_all(program, lists, string, budget, 1)?;
+    Ok(parses.remove(0).captures)
+}
+
+/// Finds up to `limit` distinct parses, in the order the first one would
+/// be found by `perform_match`. A `limit` of zero finds none, without
+/// checking whether the words match.
+pub fn perform_match_all<'a, 'c>(
+    program: &'a [Instruction],
+    lists: &'c Lists,
+    string: &'c [WordInfo],
+    budget: &MatchBudget,
+    limit: usize,
+) -> Result<Vec<Parse<'a>>> {
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut budget = Budget::new(budget);
+    let mut memo = Memo::new();
     let mut threads = Vec::new();
-    threads.push(Thread::new(program, string));
+    threads.push(Thread::new(program, lists, string));
 
+    let mut parses = Vec::new();
+    let mut found = HashSet::new();
+    // the threads that matched, including those with a parse found before
+    let mut matches = 0;
     while let Some(t) = threads.pop() {
-        let result = t.run(&mut threads).ok();
+        if parses.len() >= limit {
+            break;
+        }
+
+        if let Some(parse) = t.run(&mut threads, &mut budget, &mut memo, matches)? {
+            matches += 1;
 
-        if result.is_some() {
-            return result;
+            // different paths through the program can make the same
+            // choices and captures
+            if found.insert(parse.clone()) {
+                parses.push(parse);
+            }
         }
+
+        memo.settle(threads.len(), matches);
     }
 
-    None
+    if parses.is_empty() {
+        return Err(MatchError::NoMatch);
+    }
+
+    Ok(parses)
 }
 
-type Result<T> = ::std::result::Result<T, ()>;
+pub fn invalid<T>(message: &str) -> Result<T> {
+    Err(MatchError::InvalidProgram(message.to_owned()))
+}
+
+pub fn fetch(program: &[Instruction], program_pointer: usize) -> Result<&Instruction> {
+    match program.get(program_pointer) {
+        Some(instruction) => Ok(instruction),
+        None => invalid("program pointer out of range"),
+    }
+}
+
+pub fn address(target: &JumpTarget) -> Result<usize> {
+    match target.address() {
+        Some(address) => Ok(address),
+        None => invalid("unresolved jump target"),
+    }
+}
+
+pub fn permutation_bit(child: usize) -> Result<u64> {
+    if child < 64 {
+        Ok(1u64 << child)
+    } else {
+        invalid("permutation child out of range")
+    }
+}
+
+/// Records a guarded call of the rule at `target` at `position`, unless
+/// the rule is already being called at that position more often than
+/// there are `remaining` words.
+pub fn guard(
+    guards: &mut Vec<(usize, usize)>,
+    target: usize,
+    position: usize,
+    remaining: usize,
+) -> bool {
+    let depth = guards
+        .iter()
+        .filter(|&&(t, p)| t == target && p == position)
+        .count();
+
+    if depth > remaining {
+        return false;
+    }
+
+    guards.push((target, position));
+    true
+}
+
+// Everything about a thread that determines whether it can still match,
+// which leaves out the captures and choices it has made so far.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct StateKey {
+    program_pointer: usize,
+    string_pointer: usize,
+    call_stack: Vec<usize>,
+    progress: Vec<(usize, usize)>,
+    permutations: Vec<u64>,
+    counters: Vec<usize>,
+    suppressed: usize,
+    guards: Vec<(usize, usize)>,
+    in_dictation: bool,
+}
+
+// The children of a permutation can be claimed in every possible order,
+// and trying each of them would take factorial time. Different orders
+// that claim the same children over the same words end up in the same
+// state, so once the threads that continue from a state have all failed,
+// the state is remembered and not tried again. A thread that matches
+// does not fail, even if its parse was found before, since another
+// thread reaching the same state can make different choices.
+struct Memo {
+    failed: HashSet<StateKey>,
+    // states whose threads have not all finished yet, along with the
+    // number of threads and matches when they were reached
+    pending: Vec<(StateKey, usize, usize)>,
+}
+
+impl Memo {
+    fn new() -> Self {
+        Memo {
+            failed: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    // Called after a thread has finished. The threads continuing from a
+    // pending state are the one that reached it and the ones pushed after
+    // it, so they are done once the stack is back to its size at the
+    // time.
+    fn settle(&mut self, threads: usize, matches: usize) {
+        while let Some((key, pending_threads, pending_matches)) = self.pending.pop() {
+            if threads > pending_threads {
+                self.pending.push((key, pending_threads, pending_matches));
+                break;
+            }
+
+            if matches == pending_matches {
+                self.failed.insert(key);
+            }
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 struct Thread<'a, 'c> {
     instructions: &'a [Instruction],
+    lists: &'c Lists,
     string: &'c [WordInfo],
     program_pointer: usize,
     string_pointer: usize,
     call_stack: Vec<usize>,
     captures: CaptureBuilder<'a>,
     progress: HashMap<usize, usize>,
+    permutations: Vec<u64>,
+    counters: Vec<usize>,
+    suppressed: usize,
+    guards: Vec<(usize, usize)>,
+    choices: Vec<&'a Choice>,
+    literal_words: usize,
+    dictation_start: Option<usize>,
+    dictation_words: usize,
 }
 
 impl<'a, 'c> Thread<'a, 'c> {
-    fn new(instructions: &'a [Instruction], string: &'c [WordInfo]) -> Self {
+    fn new(instructions: &'a [Instruction], lists: &'c Lists, string: &'c [WordInfo]) -> Self {
         Thread {
             instructions: instructions,
+            lists: lists,
             string: string,
             program_pointer: 0,
             string_pointer: 0,
             call_stack: Vec::new(),
             captures: CaptureBuilder::new(),
             progress: HashMap::new(),
+            permutations: Vec::new(),
+            counters: Vec::new(),
+            suppressed: 0,
+            guards: Vec::new(),
+            choices: Vec::new(),
+            literal_words: 0,
+            dictation_start: None,
+            dictation_words: 0,
         }
     }
 
-    fn match_token(&mut self, word: Option<&'a str>) -> Result<()> {
+    fn match_token(&mut self, word: Option<&'a str>) -> bool {
         let current = self.string.get(self.string_pointer);
         if let Some(word_info) = current {
             if let Some(word) = word {
                 if word != word_info.text {
-                    return Err(());
+                    return false;
                 }
             }
 
             self.string_pointer += 1;
-            return Ok(());
+            true
         } else {
-            return Err(());
+            false
         }
     }
 
-    fn run(mut self, threads: &mut Vec<Thread<'a, 'c>>) -> Result<Vec<Match<'a>>> {
+    fn matches_entry(&self, entry: &ListEntry) -> bool {
+        let rest = &self.string[self.string_pointer..];
+
+        !entry.words.is_empty()
+            && entry.words.len() <= rest.len()
+            && entry.words.iter().zip(rest).all(|(e, w)| *e == w.text)
+    }
+
+    fn take_entry(&mut self, list: &'a str, entry: &ListEntry) {
+        let start = self.string_pointer;
+        self.string_pointer += entry.words.len();
+
+        if self.suppressed == 0 {
+            self.captures
+                .list_entry(list, &entry.text, (start, self.string_pointer));
+        }
+    }
+
+    fn state_key(&self) -> StateKey {
+        let mut progress = self
+            .progress
+            .iter()
+            .map(|(&pc, &position)| (pc, position))
+            .collect::<Vec<_>>();
+        progress.sort();
+
+        StateKey {
+            program_pointer: self.program_pointer,
+            string_pointer: self.string_pointer,
+            call_stack: self.call_stack.clone(),
+            progress: progress,
+            permutations: self.permutations.clone(),
+            counters: self.counters.clone(),
+            suppressed: self.suppressed,
+            guards: self.guards.clone(),
+            in_dictation: self.dictation_start.is_some(),
+        }
+    }
+
+    /// Returns `None` if this thread fails to match. `matches` is the
+    /// number of threads that matched so far.
+    fn run(
+        mut self,
+        threads: &mut Vec<Thread<'a, 'c>>,
+        budget: &mut Budget,
+        memo: &mut Memo,
+        matches: usize,
+    ) -> Result<Option<Parse<'a>>> {
         loop {
-            let next = &self.instructions[self.program_pointer];
+            budget.step()?;
+
+            let next = fetch(self.instructions, self.program_pointer)?;
             self.program_pointer += 1;
 
             match *next {
                 Instruction::Literal(ref grammar_word) => {
-                    self.match_token(Some(grammar_word))?;
+                    if !self.match_token(Some(grammar_word)) {
+                        return Ok(None);
+                    }
+                    self.literal_words += 1;
+                }
+                Instruction::AnyWord { .. } => {
+                    if !self.match_token(None) {
+                        return Ok(None);
+                    }
+                }
+                Instruction::AnyWordExcept(ref excluded) => {
+                    let current = self.string.get(self.string_pointer);
+                    if current.map_or(false, |w| excluded.contains(&w.text)) {
+                        return Ok(None);
+                    }
+
+                    if !self.match_token(None) {
+                        return Ok(None);
+                    }
+                }
+                Instruction::SpellingLetter => {
+                    let current = self.string.get(self.string_pointer);
+                    let letter = match current.and_then(|w| decode_letter(&w.text)) {
+                        Some(letter) => letter,
+                        None => return Ok(None),
+                    };
+
+                    if self.suppressed == 0 {
+                        self.captures.letter(self.string_pointer, letter);
+                    }
+                    self.string_pointer += 1;
                 }
-                Instruction::AnyWord => {
-                    self.match_token(None)?;
+                Instruction::List(ref name) => {
+                    // a list that has never been filled matches any word
+                    let entries = match self.lists.get(name) {
+                        Some(entries) => entries,
+                        None => {
+                            if !self.match_token(None) {
+                                return Ok(None);
+                            }
+                            continue;
+                        }
+                    };
+                    let matching = entries
+                        .iter()
+                        .filter(|e| self.matches_entry(e))
+                        .collect::<Vec<_>>();
+
+                    let (first, rest) = match matching.split_first() {
+                        Some(split) => split,
+                        None => return Ok(None),
+                    };
+
+                    for e in rest.iter().rev() {
+                        let mut branch = self.clone();
+                        branch.take_entry(name, e);
+                        threads.push(branch);
+                    }
+
+                    self.take_entry(name, first);
                 }
                 Instruction::CaptureStart(ref name) => {
-                    self.captures.capture_start(name, self.string_pointer);
+                    if self.suppressed == 0 {
+                        self.captures.capture_start(name, self.string_pointer);
+                    }
                 }
                 Instruction::CaptureStop => {
-                    self.captures.capture_stop(self.string_pointer);
+                    if self.suppressed == 0 {
+                        self.captures.capture_stop(self.string_pointer)?;
+                    }
+                }
+                Instruction::SuppressStart => {
+                    self.suppressed += 1;
+                }
+                Instruction::SuppressStop => {
+                    if self.suppressed == 0 {
+                        return invalid("suppression stopped without being started");
+                    }
+                    self.suppressed -= 1;
+                }
+                Instruction::Choice(ref choice) => {
+                    self.choices.push(choice);
+                }
+                Instruction::DictationStart => {
+                    self.dictation_start = Some(self.string_pointer);
+                }
+                Instruction::DictationStop => {
+                    let start = match self.dictation_start.take() {
+                        Some(start) => start,
+                        None => return invalid("dictation stopped without being started"),
+                    };
+                    self.dictation_words += self.string_pointer - start;
                 }
                 Instruction::Return => {
                     if let Some(return_address) = self.call_stack.pop() {
                         self.program_pointer = return_address;
                     } else if self.string_pointer == self.string.len() {
-                        return Ok(self.captures.done());
+                        return Ok(Some(Parse {
+                            captures: self.captures.done()?,
+                            choices: self.choices,
+                            literal_words: self.literal_words,
+                            dictation_words: self.dictation_words,
+                        }));
                     } else {
-                        return Err(());
+                        return Ok(None);
                     }
                 }
                 Instruction::RuleCall(ref t) => {
                     self.call_stack.push(self.program_pointer);
-                    self.program_pointer = t.address();
+                    self.program_pointer = address(t)?;
+                }
+                Instruction::GuardedCall(ref t) => {
+                    let target = address(t)?;
+                    let remaining = self.string.len() - self.string_pointer;
+
+                    if !guard(&mut self.guards, target, self.string_pointer, remaining) {
+                        return Ok(None);
+                    }
+
+                    self.call_stack.push(self.program_pointer);
+                    self.program_pointer = target;
+                }
+                Instruction::GuardRelease => {
+                    if self.guards.pop().is_none() {
+                        return invalid("guard released without being taken");
+                    }
                 }
                 Instruction::Jump(ref t) => {
-                    self.program_pointer = t.address();
+                    self.program_pointer = address(t)?;
                 }
                 Instruction::Split(ref targets) => {
-                    let (first, rest) = targets.split_first().unwrap();
+                    let (first, rest) = match targets.split_first() {
+                        Some(split) => split,
+                        None => return Ok(None),
+                    };
 
                     for t in rest.iter().rev() {
                         let mut branch = self.clone();
-                        branch.program_pointer = t.address();
+                        branch.program_pointer = address(t)?;
                         threads.push(branch);
                     }
 
-                    self.program_pointer = first.address();
+                    self.program_pointer = address(first)?;
                 }
                 Instruction::Progress => {
                     // make sure we've progressed since the last time
@@ -122,7 +443,7 @@ impl<'a, 'c> Thread<'a, 'c> {
 
                             // stop we haven't made progress
                             if current == *previous {
-                                return Err(());
+                                return Ok(None);
                             }
 
                             *previous = current;
@@ -132,6 +453,63 @@ impl<'a, 'c> Thread<'a, 'c> {
                         }
                     }
                 }
+                Instruction::PermutationStart => {
+                    self.permutations.push(0);
+                }
+                Instruction::PermutationClaim(child) => {
+                    let bit = permutation_bit(child)?;
+                    let claimed = match self.permutations.last_mut() {
+                        Some(claimed) => claimed,
+                        None => return invalid("permutation claimed outside of permutation"),
+                    };
+
+                    if *claimed & bit != 0 {
+                        return Ok(None);
+                    }
+
+                    *claimed |= bit;
+
+                    let key = self.state_key();
+                    if memo.failed.contains(&key) {
+                        return Ok(None);
+                    }
+                    memo.pending.push((key, threads.len(), matches));
+                }
+                Instruction::PermutationEnd {
+                    all_required,
+                    count,
+                } => {
+                    let claimed = match self.permutations.pop() {
+                        Some(claimed) => claimed,
+                        None => return invalid("permutation ended without being started"),
+                    };
+                    let matched = claimed.count_ones() as usize;
+
+                    if matched == 0 {
+                        return Ok(None);
+                    }
+
+                    if all_required && matched != count {
+                        return Ok(None);
+                    }
+                }
+                Instruction::CounterStart => {
+                    self.counters.push(0);
+                }
+                Instruction::CounterBelow(limit) => match self.counters.last() {
+                    Some(&count) if count < limit => {}
+                    Some(_) => return Ok(None),
+                    None => return invalid("counter checked without being started"),
+                },
+                Instruction::CounterIncrement => match self.counters.last_mut() {
+                    Some(count) => *count += 1,
+                    None => return invalid("counter incremented without being started"),
+                },
+                Instruction::CounterStop => {
+                    if self.counters.pop().is_none() {
+                        return invalid("counter stopped without being started");
+                    }
+                }
                 Instruction::NoOp | Instruction::Label(_) => {}
             }
         }